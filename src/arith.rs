@@ -0,0 +1,437 @@
+use crate::{cmd::Col, error::CommandError, vars::Variables};
+
+/// Operators, longest first so that tokenizing can stop at the first match.
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "++", "--", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", ",", "(", ")",
+];
+
+/// Binary operators from the loosest to the tightest binding one, `**`
+/// excepted as it is right associative.
+const LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+const ASSIGNMENTS: [&str; 11] = [
+    "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+/// Variables referring to other expressions are evaluated up to this depth.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+fn error(message: impl Into<String>, col: usize) -> CommandError {
+    CommandError::Arithmetic(message.into(), Col(col as u32))
+}
+
+/// Parses an integer constant: decimal, `0x` hexadecimal, `0` octal or
+/// `base#digits`.
+fn parse_number(text: &str) -> Option<i64> {
+    let (digits, base) = if let Some((base, digits)) = text.split_once('#') {
+        (digits, base.parse().ok().filter(|b| (2..=64).contains(b))?)
+    } else if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        (hex, 16)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (&text[1..], 8)
+    } else {
+        (text, 10)
+    };
+
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as i64 - '0' as i64,
+            'a'..='z' => c as i64 - 'a' as i64 + 10,
+            'A'..='Z' if base <= 36 => c as i64 - 'A' as i64 + 10,
+            'A'..='Z' => c as i64 - 'A' as i64 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return None,
+        };
+        if digit >= base {
+            return None;
+        }
+        value = value.checked_mul(base)?.checked_add(digit)?;
+    }
+    (!digits.is_empty()).then_some(value)
+}
+
+fn tokenize(expr: &str) -> Result<Vec<(Token, usize)>, CommandError> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let len = chars[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '#' | '@'))
+                .count();
+            let word: String = chars[i..i + len].iter().collect();
+            let token = if c.is_ascii_digit() {
+                Token::Number(
+                    parse_number(&word)
+                        .ok_or_else(|| error(format!("invalid number `{word}`"), i))?,
+                )
+            } else if word.contains(['#', '@']) {
+                return Err(error(format!("syntax error in `{word}`"), i));
+            } else {
+                Token::Name(word)
+            };
+            tokens.push((token, i));
+            i += len;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) else {
+                return Err(error(format!("syntax error: invalid character `{c}`"), i));
+            };
+            tokens.push((Token::Op(op), i));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Evaluator<'a> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Column right after the expression, for errors at its end.
+    end: usize,
+    vars: &'a mut Variables,
+    /// Non-zero while parsing an operand that short-circuiting skips: nothing
+    /// is assigned and no error is raised there.
+    skip: usize,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn col(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(_, col)| *col)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some((Token::Op(op), _)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), CommandError> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(error(format!("syntax error: `{op}` expected"), self.col()))
+        }
+    }
+
+    fn value(&mut self, name: &str, col: usize) -> Result<i64, CommandError> {
+        let text = self.vars.get(name).unwrap_or_default().trim().to_string();
+        if text.is_empty() {
+            return Ok(0);
+        }
+        if let Some(value) = parse_number(&text) {
+            return Ok(value);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(error("expression recursion level exceeded", col));
+        }
+
+        let tokens =
+            tokenize(&text).map_err(|_| error(format!("`{name}` is not a number"), col))?;
+        let mut nested = Evaluator {
+            tokens,
+            pos: 0,
+            end: text.len(),
+            vars: &mut *self.vars,
+            skip: self.skip,
+            depth: self.depth + 1,
+        };
+        nested.evaluate()
+    }
+
+    fn assign(&mut self, name: &str, value: i64) -> Result<(), CommandError> {
+        if self.skip == 0 {
+            self.vars.set(name, value.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Applies a binary operator, `col` being where it was written.
+    fn apply(&self, op: &str, lhs: i64, rhs: i64, col: usize) -> Result<i64, CommandError> {
+        let result = match op {
+            "+" => lhs.checked_add(rhs),
+            "-" => lhs.checked_sub(rhs),
+            "*" => lhs.checked_mul(rhs),
+            "/" | "%" if rhs == 0 => {
+                return match self.skip {
+                    0 => Err(error("division by zero", col)),
+                    _ => Ok(0),
+                }
+            }
+            "/" => lhs.checked_div(rhs),
+            "%" => lhs.checked_rem(rhs),
+            "**" if rhs < 0 => {
+                return match self.skip {
+                    0 => Err(error("exponent less than 0", col)),
+                    _ => Ok(0),
+                }
+            }
+            "**" => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_pow(rhs)),
+            "<<" => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shl(rhs)),
+            ">>" => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shr(rhs)),
+            "<" => Some((lhs < rhs) as i64),
+            "<=" => Some((lhs <= rhs) as i64),
+            ">" => Some((lhs > rhs) as i64),
+            ">=" => Some((lhs >= rhs) as i64),
+            "==" => Some((lhs == rhs) as i64),
+            "!=" => Some((lhs != rhs) as i64),
+            "&" => Some(lhs & rhs),
+            "^" => Some(lhs ^ rhs),
+            "|" => Some(lhs | rhs),
+            _ => unreachable!("not a binary operator: {op}"),
+        };
+
+        match result {
+            Some(value) => Ok(value),
+            None if self.skip > 0 => Ok(0),
+            None => Err(error("integer overflow", col)),
+        }
+    }
+
+    fn evaluate(&mut self) -> Result<i64, CommandError> {
+        if self.tokens.is_empty() {
+            return Ok(0);
+        }
+        let value = self.comma()?;
+        if self.pos < self.tokens.len() {
+            return Err(error("syntax error in expression", self.col()));
+        }
+        Ok(value)
+    }
+
+    fn comma(&mut self) -> Result<i64, CommandError> {
+        let mut value = self.assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self) -> Result<i64, CommandError> {
+        let target = match &self.tokens[self.pos..] {
+            [(Token::Name(name), _), (Token::Op(op), col), ..] if ASSIGNMENTS.contains(op) => {
+                Some((name.clone(), *op, *col))
+            }
+            _ => None,
+        };
+        let Some((name, op, col)) = target else {
+            return self.ternary();
+        };
+
+        self.pos += 2;
+        let rhs = self.assignment()?;
+        let value = match op {
+            "=" => rhs,
+            _ => {
+                let lhs = self.value(&name, col)?;
+                self.apply(&op[..op.len() - 1], lhs, rhs, col)?
+            }
+        };
+        self.assign(&name, value)?;
+        Ok(value)
+    }
+
+    fn ternary(&mut self) -> Result<i64, CommandError> {
+        let condition = self.binary(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(condition);
+        }
+
+        self.pos += 1;
+        self.skip += (condition == 0) as usize;
+        let then = self.assignment();
+        self.skip -= (condition == 0) as usize;
+        let then = then?;
+        self.expect(":")?;
+        self.skip += (condition != 0) as usize;
+        let otherwise = self.ternary();
+        self.skip -= (condition != 0) as usize;
+        let otherwise = otherwise?;
+
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, CommandError> {
+        let Some(ops) = LEVELS.get(level) else {
+            return self.power();
+        };
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| ops.contains(op)) {
+            let col = self.col();
+            self.pos += 1;
+            if matches!(op, "&&" | "||") {
+                let decided = (op == "&&" && lhs == 0) || (op == "||" && lhs != 0);
+                self.skip += decided as usize;
+                let rhs = self.binary(level + 1);
+                self.skip -= decided as usize;
+                let rhs = rhs?;
+                lhs = if decided { lhs != 0 } else { rhs != 0 } as i64;
+            } else {
+                let rhs = self.binary(level + 1)?;
+                lhs = self.apply(op, lhs, rhs, col)?;
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn power(&mut self) -> Result<i64, CommandError> {
+        let base = self.unary()?;
+        if self.peek_op() != Some("**") {
+            return Ok(base);
+        }
+
+        let col = self.col();
+        self.pos += 1;
+        let exponent = self.power()?;
+        self.apply("**", base, exponent, col)
+    }
+
+    fn unary(&mut self) -> Result<i64, CommandError> {
+        let col = self.col();
+        match self.peek_op() {
+            Some(op @ ("!" | "~" | "-" | "+")) => {
+                self.pos += 1;
+                let value = self.unary()?;
+                match op {
+                    "!" => Ok((value == 0) as i64),
+                    "~" => Ok(!value),
+                    "-" => self.apply("-", 0, value, col),
+                    _ => Ok(value),
+                }
+            }
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                let Some((Token::Name(name), _)) = self.tokens.get(self.pos).cloned() else {
+                    return Err(error(format!("syntax error: `{op}` needs a variable"), col));
+                };
+                self.pos += 1;
+                let value = self.value(&name, col)?;
+                let value = self.apply(&op[..1], value, 1, col)?;
+                self.assign(&name, value)?;
+                Ok(value)
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<i64, CommandError> {
+        let col = self.col();
+        match self.tokens.get(self.pos).cloned() {
+            Some((Token::Number(value), _)) => {
+                self.pos += 1;
+                Ok(value)
+            }
+            Some((Token::Name(name), _)) => {
+                self.pos += 1;
+                let value = self.value(&name, col)?;
+                if let Some(op @ ("++" | "--")) = self.peek_op() {
+                    self.pos += 1;
+                    let updated = self.apply(&op[..1], value, 1, col)?;
+                    self.assign(&name, updated)?;
+                }
+                Ok(value)
+            }
+            Some((Token::Op("("), _)) => {
+                self.pos += 1;
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => Err(error("syntax error: operand expected", col)),
+        }
+    }
+}
+
+/// Evaluates the integer expression of a `$(( ))` or `(( ))`. Names refer to
+/// shell variables, which assignments update.
+pub fn evaluate(expr: &str, vars: &mut Variables) -> Result<i64, CommandError> {
+    let mut evaluator = Evaluator {
+        tokens: tokenize(expr)?,
+        pos: 0,
+        end: expr.chars().count(),
+        vars,
+        skip: 0,
+        depth: 0,
+    };
+    evaluator.evaluate()
+}
+
+#[test]
+fn test_evaluate() {
+    let mut vars = Variables::new();
+    let mut eval = |expr: &str| evaluate(expr, &mut vars).unwrap();
+
+    assert_eq!(eval("1 + 2 * 3"), 7);
+    assert_eq!(eval("(1 + 2) * 3"), 9);
+    assert_eq!(eval("2 ** 3 ** 2"), 512);
+    assert_eq!(eval("-7 / 2 + -7 % 2"), -4);
+    assert_eq!(eval("0x1f | 010 ^ 2#11"), 31);
+    assert_eq!(eval("1 < 2 && 2 >= 3 || !0"), 1);
+    assert_eq!(eval("~0 << 4 >> 2"), -4);
+    assert_eq!(eval("x = 5, x += 2, x * 2"), 14);
+    assert_eq!(eval("x++ + ++x"), 16);
+    assert_eq!(eval("x ? x-- : 1"), 9);
+    assert_eq!(eval("y = x > 5 ? 10 : 20"), 10);
+    assert_eq!(eval(""), 0);
+    assert_eq!(vars.get("x"), Some("8"));
+    assert_eq!(vars.get("y"), Some("10"));
+
+    vars.set("e", "x + 1".into()).unwrap();
+    assert_eq!(evaluate("e * 2", &mut vars).unwrap(), 18);
+    assert_eq!(evaluate("0 && 1 / 0", &mut vars).unwrap(), 0);
+    assert_eq!(evaluate("1 || (z = 1)", &mut vars).unwrap(), 1);
+    assert_eq!(vars.get("z"), None);
+}
+
+#[test]
+fn test_evaluate_errors() {
+    let mut vars = Variables::new();
+
+    assert!(matches!(
+        evaluate("1 + 4 / 0", &mut vars),
+        Err(CommandError::Arithmetic(_, Col(6)))
+    ));
+    assert!(matches!(
+        evaluate("9223372036854775807 + 1", &mut vars),
+        Err(CommandError::Arithmetic(_, Col(20)))
+    ));
+    assert!(matches!(
+        evaluate("1 +", &mut vars),
+        Err(CommandError::Arithmetic(_, Col(3)))
+    ));
+    assert!(evaluate("(1", &mut vars).is_err());
+    assert!(evaluate("08", &mut vars).is_err());
+}
@@ -1,363 +1,1959 @@
-use std::{collections::HashMap, env, iter::Peekable};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    iter::Peekable,
+    rc::Rc,
+};
 
-use crate::error::CmdParsingError;
+use crate::{
+    error::{CmdParsingError, CommandError},
+    exec::Executor,
+    expand::{expand_assignment, expand_heredoc, expand_vars_into_arg, expand_word},
+    vars::is_valid_name,
+};
 
 #[derive(Debug)]
 pub struct Cmd {
     pub variables_overrides: HashMap<String, String>,
     pub name: String,
     pub args: Vec<String>,
+    pub redirects: Vec<Redirect>,
 }
 
-enum ParsingState {
-    Vars,
-    Args,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+    /// `<file`
+    Input,
+    /// `>file`
+    Output,
+    /// `>>file`
+    Append,
+    /// `<&N`, `<&-`
+    DupInput,
+    /// `>&N`, `>&-`
+    DupOutput,
+    /// `<<EOF`, the target holds the body. Quoting the delimiter turns
+    /// expansion off.
+    HereDoc { expand: bool },
+    /// `<<<word`
+    HereString,
 }
 
-#[derive(Debug)]
-enum ArgReplacementState {
-    ParsingVar(Vec<char>),
-    IsEscaped,
-    Next,
-}
-
-fn expand_vars_into_arg(override_maps: &HashMap<String, String>, arg: &str) -> String {
-    let mut final_arg: String = String::new();
-    let mut state = ArgReplacementState::Next;
-    for character in arg.chars() {
-        if character == '$' {
-            match state {
-                ArgReplacementState::ParsingVar(xs) => {
-                    let var_string = xs
-                        .iter()
-                        .map(|x| x.to_string())
-                        .collect::<Vec<String>>()
-                        .join("");
-                    if let Some(value) = override_maps.get(&var_string) {
-                        final_arg.push_str(value);
-                    } else {
-                        if let Ok(value) = env::var(&var_string) {
-                            final_arg.push_str(&value);
-                        } else {
-                            final_arg.push_str("");
-                        }
-                    }
+/// `fd` is the descriptor being redirected and `target` either a file name,
+/// the text fed by a here-document/string or, for the dup kinds, a
+/// descriptor number or `-` to close it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub kind: RedirectKind,
+    pub target: String,
+}
 
-                    state = ArgReplacementState::ParsingVar(vec![]);
-                }
-                ArgReplacementState::IsEscaped => {
-                    final_arg.push(character);
-                    state = ArgReplacementState::Next;
-                }
-                ArgReplacementState::Next => {
-                    state = ArgReplacementState::ParsingVar(vec![]);
-                }
-            }
-        } else if character == '\\' {
-            match state {
-                ArgReplacementState::ParsingVar(xs) => {
-                    if character.is_ascii_alphanumeric() || character == '_' {
-                        let mut xs = xs;
-                        xs.push(character);
-                        state = ArgReplacementState::ParsingVar(xs);
-                    } else {
-                        let var_string = xs
-                            .iter()
-                            .map(|x| x.to_string())
-                            .collect::<Vec<String>>()
-                            .join("");
-                        if let Some(value) = override_maps.get(&var_string) {
-                            final_arg.push_str(value);
-                        } else {
-                            if let Ok(value) = env::var(&var_string) {
-                                final_arg.push_str(&value);
-                            } else {
-                                final_arg.push_str("");
-                            }
-                        }
+/// A simple command as written on the line: assignments and words are kept
+/// raw (quotes and `$` untouched) and only expanded right before running.
+#[derive(Debug, Clone, Default)]
+pub struct SimpleCmd {
+    pub assignments: Vec<(String, String)>,
+    pub words: Vec<String>,
+    /// Column of the line each of `words` starts at.
+    pub word_cols: Vec<Col>,
+    pub redirects: Vec<Redirect>,
+}
 
-                        state = ArgReplacementState::IsEscaped;
-                    }
-                }
-                ArgReplacementState::IsEscaped => {
-                    final_arg.push(character);
-                    state = ArgReplacementState::Next;
+/// Where the words were on the line does not make another command.
+impl PartialEq for SimpleCmd {
+    fn eq(&self, other: &Self) -> bool {
+        self.assignments == other.assignments
+            && self.words == other.words
+            && self.redirects == other.redirects
+    }
+}
+
+impl Eq for SimpleCmd {}
+
+/// A command run as a whole rather than as a program and its arguments.
+/// Words are kept raw as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compound {
+    /// `{ list; }`
+    Group(CmdList),
+    /// `(( expr ))`, and the column of the line the expression starts at.
+    Arith(String, Col),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`, as
+    /// the condition and body of each branch.
+    If {
+        branches: Vec<(CmdList, CmdList)>,
+        otherwise: Option<CmdList>,
+    },
+    /// `while list; do list; done`, or `until` when `until` is set.
+    While {
+        until: bool,
+        condition: CmdList,
+        body: CmdList,
+    },
+    /// `for name [in word...]; do list; done`, without words looping over
+    /// the positional parameters.
+    For {
+        var: String,
+        words: Option<Vec<String>>,
+        body: CmdList,
+    },
+    /// `for ((init; condition; step)); do list; done`
+    ArithFor {
+        init: String,
+        condition: String,
+        step: String,
+        body: CmdList,
+    },
+    /// `case word in [(]pattern[|pattern]...) list;; ... esac`
+    Case {
+        word: String,
+        arms: Vec<(Vec<String>, CmdList)>,
+    },
+}
+
+/// One stage of a pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCmd),
+    /// A compound command and the redirections following it.
+    Compound(Compound, Vec<Redirect>),
+    /// `name() compound` or `function name compound`, which defines the
+    /// function rather than running it. The body is shared with the
+    /// function table so that it outlives the line.
+    Function(String, Rc<Command>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    pub cmds: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+/// `a && b || c`, optionally sent to the background with a trailing `&`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOrList {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    pub background: bool,
+}
+
+pub type CmdList = Vec<AndOrList>;
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (default_fd, op) = match self.kind {
+            RedirectKind::Input => (0, "<"),
+            RedirectKind::Output => (1, ">"),
+            RedirectKind::Append => (1, ">>"),
+            RedirectKind::DupInput => (0, "<&"),
+            RedirectKind::DupOutput => (1, ">&"),
+            RedirectKind::HereDoc { .. } => return write!(f, "<<EOF"),
+            RedirectKind::HereString => (0, "<<<"),
+        };
+        if self.fd != default_fd {
+            write!(f, "{}", self.fd)?;
+        }
+        write!(f, "{op}{}", self.target)
+    }
+}
+
+impl fmt::Display for SimpleCmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let assignments = self.assignments.iter().map(|(k, v)| format!("{k}={v}"));
+        let redirects = self.redirects.iter().map(Redirect::to_string);
+        let parts: Vec<String> = assignments
+            .chain(self.words.iter().cloned())
+            .chain(redirects)
+            .collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Writes the lists of a compound command on one line, each one ended by
+/// `;` unless it is sent to the background.
+fn write_list(f: &mut fmt::Formatter<'_>, list: &CmdList) -> fmt::Result {
+    for and_or in list {
+        let separator = if and_or.background { " " } else { "; " };
+        write!(f, "{and_or}{separator}")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Compound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compound::Group(list) => {
+                write!(f, "{{ ")?;
+                write_list(f, list)?;
+                write!(f, "}}")
+            }
+            Compound::Arith(expr, _) => write!(f, "(({expr}))"),
+            Compound::If {
+                branches,
+                otherwise,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    write!(f, "{} ", if i == 0 { "if" } else { "elif" })?;
+                    write_list(f, condition)?;
+                    write!(f, "then ")?;
+                    write_list(f, body)?;
                 }
-                ArgReplacementState::Next => {
-                    state = ArgReplacementState::IsEscaped;
+                if let Some(otherwise) = otherwise {
+                    write!(f, "else ")?;
+                    write_list(f, otherwise)?;
                 }
+                write!(f, "fi")
             }
-        } else {
-            match state {
-                ArgReplacementState::ParsingVar(xs) => {
-                    if character.is_ascii_alphanumeric() || character == '_' {
-                        let mut xs = xs;
-                        xs.push(character);
-                        state = ArgReplacementState::ParsingVar(xs);
-                    } else {
-                        let var_string = xs
-                            .iter()
-                            .map(|x| x.to_string())
-                            .collect::<Vec<String>>()
-                            .join("");
-                        if let Some(value) = override_maps.get(&var_string) {
-                            final_arg.push_str(value);
-                        } else {
-                            if let Ok(value) = env::var(&var_string) {
-                                final_arg.push_str(&value);
-                            } else {
-                                final_arg.push_str("");
-                            }
-                        }
-
-                        state = ArgReplacementState::Next;
+            Compound::While {
+                until,
+                condition,
+                body,
+            } => {
+                write!(f, "{} ", if *until { "until" } else { "while" })?;
+                write_list(f, condition)?;
+                write!(f, "do ")?;
+                write_list(f, body)?;
+                write!(f, "done")
+            }
+            Compound::For { var, words, body } => {
+                write!(f, "for {var}")?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    for word in words {
+                        write!(f, " {word}")?;
                     }
                 }
-                ArgReplacementState::IsEscaped => {
-                    final_arg.push(character);
-                    state = ArgReplacementState::Next;
-                }
-                ArgReplacementState::Next => {
-                    final_arg.push(character);
-                    state = ArgReplacementState::Next;
+                write!(f, "; do ")?;
+                write_list(f, body)?;
+                write!(f, "done")
+            }
+            Compound::ArithFor {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                write!(f, "for (({init};{condition};{step})); do ")?;
+                write_list(f, body)?;
+                write!(f, "done")
+            }
+            Compound::Case { word, arms } => {
+                write!(f, "case {word} in ")?;
+                for (patterns, body) in arms {
+                    write!(f, "{}) ", patterns.join("|"))?;
+                    write_list(f, body)?;
+                    write!(f, ";; ")?;
                 }
+                write!(f, "esac")
             }
         }
     }
+}
 
-    match state {
-        ArgReplacementState::ParsingVar(xs) => {
-            let var_string = xs
-                .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<String>>()
-                .join("");
-            if let Some(value) = override_maps.get(&var_string) {
-                final_arg.push_str(value);
-            } else {
-                if let Ok(value) = env::var(&var_string) {
-                    final_arg.push_str(&value);
-                } else {
-                    final_arg.push_str("");
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Simple(cmd) => write!(f, "{cmd}"),
+            Command::Compound(compound, redirects) => {
+                write!(f, "{compound}")?;
+                for redirect in redirects {
+                    write!(f, " {redirect}")?;
                 }
+                Ok(())
             }
-        }
-        ArgReplacementState::IsEscaped => {
-            // FIXME: syntax error
-        }
-        ArgReplacementState::Next => {
-            // FIXME: unreachable
+            Command::Function(name, body) => write!(f, "{name}() {body}"),
         }
     }
-
-    final_arg
 }
 
-fn string_match(s: &str) -> String {
-    if s.starts_with('\'') && s.ends_with('\'') {
-        let s = s.trim_start_matches('\'').trim_end_matches('\'');
-        s.to_string()
-    } else if s.starts_with('"') && s.ends_with('"') {
-        let s = s.trim_start_matches('"').trim_end_matches('"');
-        s.to_string()
-    } else {
-        s.to_string()
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cmds: Vec<String> = self.cmds.iter().map(Command::to_string).collect();
+        write!(f, "{}", cmds.join(" | "))
     }
 }
 
-#[test]
-fn test_expand_var_simple() {
-    let mut overridemap = HashMap::new();
-    overridemap.insert("VAR".to_string(), "Nothing".to_string());
-    let new_arg = expand_vars_into_arg(&overridemap, "$VAR");
-
-    assert_eq!(new_arg, "Nothing".to_string());
-}
-
-#[test]
-fn test_expand_var_double() {
-    let mut overridemap = HashMap::new();
-    overridemap.insert("VAR".to_string(), "Nothing".to_string());
-    let new_arg = expand_vars_into_arg(&overridemap, "$VAR$VAR");
-
-    assert_eq!(new_arg, "NothingNothing".to_string());
-}
-
-#[test]
-fn test_expand_var_escape() {
-    let mut overridemap = HashMap::new();
-    overridemap.insert("VAR".to_string(), "Nothing".to_string());
-    let new_arg = expand_vars_into_arg(&overridemap, "$VAR\\$VAR");
-
-    assert_eq!(new_arg, "Nothing$VAR".to_string());
+impl fmt::Display for AndOrList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            match connector {
+                Connector::And => write!(f, " && {pipeline}")?,
+                Connector::Or => write!(f, " || {pipeline}")?,
+            }
+        }
+        if self.background {
+            write!(f, " &")?;
+        }
+        Ok(())
+    }
 }
 
-struct CmdParser;
+pub struct CmdParser;
 
-#[derive(Debug)]
-pub struct Col(u32);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Col(pub u32);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Sym {
     PIPE,
-    EQUAL,
+    AND,
+    OR,
+    AMPERSAND,
+    SEMICOLON,
+    DSEMI,
+    NEWLINE,
+    LPAREN,
+    RPAREN,
+    LESS,
+    GREAT,
+    DGREAT,
+    LESSAND,
+    GREATAND,
+    ANDGREAT,
+    ANDDGREAT,
+    DLESS,
+    DLESSDASH,
+    TLESS,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum Token {
     Word(String, Col),
     Symbol(Sym, Col),
-    Str(String, Col),
+    /// The `2` in `2>file`.
+    IoNumber(i32, Col),
+    /// Body of a here-document, and whether it is subject to expansion.
+    /// Takes the place of the delimiter word following `<<`.
+    HereDoc(String, bool, Col),
+    /// The expression of an `(( expr ))` command.
+    Arith(String, Col),
+}
+
+fn is_word_boundary(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '|' | '&' | ';' | '<' | '>' | '(' | ')')
 }
 
-fn make_word(it: &mut Peekable<std::str::Chars<'_>>, col: &mut u32) -> Token {
+fn make_word(
+    it: &mut Peekable<std::str::Chars<'_>>,
+    col: &mut u32,
+) -> Result<Token, CmdParsingError> {
+    let start = *col;
     let mut word = String::new();
-    let mut is_escaped = false;
 
-    let first_char = it.next().unwrap();
-    if first_char == '$' || first_char.is_alphanumeric() || first_char == '_' {
-        word.push(first_char);
+    while let Some(&c) = it.peek() {
+        if is_word_boundary(c) {
+            break;
+        }
+
+        match c {
+            '\\' => {
+                word.push(it.next().unwrap());
+                *col += 1;
+                if let Some(escaped) = it.next() {
+                    word.push(escaped);
+                    *col += 1;
+                }
+            }
+            '"' | '\'' => word.push_str(&build_string(it, col, c)?),
+            '$' => {
+                word.push(it.next().unwrap());
+                *col += 1;
+                if matches!(it.peek(), Some('{' | '(')) {
+                    word.push_str(&build_expansion(it, col)?);
+                }
+            }
+            '`' => word.push_str(&build_backquoted(it, col)?),
+            _ => {
+                word.push(it.next().unwrap());
+                *col += 1;
+            }
+        }
     }
 
-    while let Some(c) = it.peek() {
-        if is_escaped {
-            word.push(it.next().unwrap());
-            is_escaped = false;
+    Ok(Token::Word(word, Col(start)))
+}
+
+/// Reads a quoted section, delimiters included, so that expansion can later
+/// tell quoted text apart from bare text. Substitutions inside double quotes
+/// are read whole as their own quotes do not end the string.
+fn build_string(
+    it: &mut Peekable<std::str::Chars<'_>>,
+    col: &mut u32,
+    del: char,
+) -> Result<String, CmdParsingError> {
+    let start = *col;
+    let mut string = String::new();
+    string.push(it.next().unwrap());
+    *col += 1;
+
+    while let Some(&c) = it.peek() {
+        if del == '"' && c == '`' {
+            string.push_str(&build_backquoted(it, col)?);
             continue;
         }
 
-        if c.is_alphanumeric() || *c == '_' {
-            word.push(it.next().unwrap());
-        } else if *c == '\\' && !is_escaped {
-            *col += 1;
-            is_escaped = true;
-            it.next();
-            continue;
-        } else {
-            break;
+        string.push(c);
+        it.next();
+        *col += 1;
+        if c == del {
+            return Ok(string);
+        }
+        if del == '"' {
+            match c {
+                '\\' => {
+                    if let Some(escaped) = it.next() {
+                        string.push(escaped);
+                        *col += 1;
+                    }
+                }
+                '$' if matches!(it.peek(), Some('{' | '(')) => {
+                    string.push_str(&build_expansion(it, col)?)
+                }
+                _ => {}
+            }
         }
     }
 
-    let len = word.len() as u32;
-    let tok = Token::Word(word, Col(*col));
-    *col += len;
-    return tok;
+    Err(CmdParsingError::UnterminatedString(del, Col(start)))
 }
 
-fn build_string(it: &mut Peekable<std::str::Chars<'_>>, col: &mut u32, del: char) -> Token {
-    let mut string = String::new();
-    let mut is_escaped = false;
-    it.next().unwrap();
+/// Reads the `{...}` or `(...)` following a `$` whole, nested expansions and
+/// quotes included, so that blanks and operators inside it do not end the
+/// word.
+fn build_expansion(
+    it: &mut Peekable<std::str::Chars<'_>>,
+    col: &mut u32,
+) -> Result<String, CmdParsingError> {
+    let start = *col - 1;
+    let (open, close, opening) = match it.peek() {
+        Some('(') => ('(', ')', "$("),
+        _ => ('{', '}', "${"),
+    };
+    let mut text = String::new();
+    let mut depth = 0;
+
+    while let Some(&c) = it.peek() {
+        match c {
+            '"' | '\'' => {
+                text.push_str(&build_string(it, col, c)?);
+                continue;
+            }
+            '`' => {
+                text.push_str(&build_backquoted(it, col)?);
+                continue;
+            }
+            '\\' => {
+                text.push(c);
+                it.next();
+                *col += 1;
+                if let Some(escaped) = it.next() {
+                    text.push(escaped);
+                    *col += 1;
+                }
+                continue;
+            }
+            _ if c == open => depth += 1,
+            _ if c == close => depth -= 1,
+            _ => {}
+        }
+        text.push(c);
+        it.next();
+        *col += 1;
+        if depth == 0 {
+            return Ok(text);
+        }
+    }
+
+    Err(CmdParsingError::UnterminatedExpansion(opening, Col(start)))
+}
+
+/// Reads a `` `...` `` command substitution, backquotes included.
+fn build_backquoted(
+    it: &mut Peekable<std::str::Chars<'_>>,
+    col: &mut u32,
+) -> Result<String, CmdParsingError> {
+    let start = *col;
+    let mut text = String::new();
+    text.push(it.next().unwrap());
+    *col += 1;
 
     while let Some(c) = it.next() {
-        if c == del && !is_escaped {
-            break;
+        text.push(c);
+        *col += 1;
+        match c {
+            '`' => return Ok(text),
+            '\\' => {
+                if let Some(escaped) = it.next() {
+                    text.push(escaped);
+                    *col += 1;
+                }
+            }
+            _ => {}
         }
-        if c == '\\' && !is_escaped {
-            *col += 1;
-            is_escaped = true;
-            continue;
+    }
+
+    Err(CmdParsingError::UnterminatedString('`', Col(start)))
+}
+
+/// Reads an `(( expr ))` command up to the `))` matching its opening, and
+/// returns the expression between them.
+fn build_arith(
+    it: &mut Peekable<std::str::Chars<'_>>,
+    col: &mut u32,
+) -> Result<Token, CmdParsingError> {
+    let start = *col;
+    it.nth(1);
+    *col += 2;
+    let mut expr = String::new();
+    let mut depth = 0;
+
+    while let Some(c) = it.next() {
+        *col += 1;
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 && it.peek() == Some(&')') => {
+                it.next();
+                *col += 1;
+                return Ok(Token::Arith(expr, Col(start)));
+            }
+            ')' => depth -= 1,
+            _ => {}
         }
-        string.push(c);
-        is_escaped = false;
+        expr.push(c);
     }
 
-    let len = string.len() + 2;
-    let tok = Token::Str(string, Col(*col));
-    *col += len as u32;
-    return tok;
+    Err(CmdParsingError::UnterminatedExpansion("((", Col(start)))
+}
+
+/// Removes quoting from a here-document delimiter and tells whether there
+/// was any.
+fn heredoc_delimiter(word: &str) -> (String, bool) {
+    let delimiter: String = word
+        .chars()
+        .filter(|c| !matches!(c, '\'' | '"' | '\\'))
+        .collect();
+    let quoted = delimiter.len() != word.len();
+    (delimiter, quoted)
+}
+
+/// Reads here-document lines up to the `delimiter` line.
+fn read_heredoc(
+    it: &mut Peekable<std::str::Chars<'_>>,
+    col: &mut u32,
+    delimiter: &str,
+    strip_tabs: bool,
+) -> Option<String> {
+    let mut body = String::new();
+    loop {
+        it.peek()?;
+
+        let mut line = String::new();
+        for c in it.by_ref() {
+            *col += 1;
+            if c == '\n' {
+                break;
+            }
+            line.push(c);
+        }
+
+        let line = if strip_tabs {
+            line.trim_start_matches('\t')
+        } else {
+            &line
+        };
+        if line == delimiter {
+            return Some(body);
+        }
+        body.push_str(line);
+        body.push('\n');
+    }
 }
 
 impl Token {
     pub fn tokenize(line: &str) -> Result<Vec<Token>, CmdParsingError> {
-        let mut it = line.chars().into_iter().peekable();
+        let mut it = line.chars().peekable();
         let mut tokens = vec![];
         let mut col: u32 = 0;
+        // Index of each `<<` delimiter token whose body starts after the next
+        // newline, and whether tabs are stripped.
+        let mut pending_heredocs: Vec<(usize, bool)> = vec![];
 
-        while let Some(c) = it.peek() {
+        while let Some(&c) = it.peek() {
             match c {
-                ' ' => {
-                    col += 1;
+                '\n' => {
+                    tokens.push(Token::Symbol(Sym::NEWLINE, Col(col)));
                     it.next();
+                    col += 1;
+                    for (index, strip_tabs) in pending_heredocs.drain(..) {
+                        let Token::Word(word, start) = &tokens[index] else {
+                            continue;
+                        };
+                        let (delimiter, quoted) = heredoc_delimiter(word);
+                        let start = *start;
+                        let body = read_heredoc(&mut it, &mut col, &delimiter, strip_tabs)
+                            .ok_or(CmdParsingError::UnterminatedHereDoc(delimiter, start))?;
+                        tokens[index] = Token::HereDoc(body, !quoted, start);
+                    }
                 }
-                '=' => {
-                    tokens.push(Token::Symbol(Sym::EQUAL, Col(col)));
-                    it.next();
+                c if c.is_whitespace() => {
                     col += 1;
+                    it.next();
+                }
+                // A comment runs to the end of the line.
+                '#' => {
+                    while it.next_if(|c| *c != '\n').is_some() {
+                        col += 1;
+                    }
                 }
                 '|' => {
-                    tokens.push(Token::Symbol(Sym::PIPE, Col(col)));
                     it.next();
-                    col += 1;
+                    if it.next_if_eq(&'|').is_some() {
+                        tokens.push(Token::Symbol(Sym::OR, Col(col)));
+                        col += 2;
+                    } else {
+                        tokens.push(Token::Symbol(Sym::PIPE, Col(col)));
+                        col += 1;
+                    }
+                }
+                '&' => {
+                    it.next();
+                    if it.next_if_eq(&'&').is_some() {
+                        tokens.push(Token::Symbol(Sym::AND, Col(col)));
+                        col += 2;
+                    } else if it.next_if_eq(&'>').is_some() {
+                        if it.next_if_eq(&'>').is_some() {
+                            tokens.push(Token::Symbol(Sym::ANDDGREAT, Col(col)));
+                            col += 3;
+                        } else {
+                            tokens.push(Token::Symbol(Sym::ANDGREAT, Col(col)));
+                            col += 2;
+                        }
+                    } else {
+                        tokens.push(Token::Symbol(Sym::AMPERSAND, Col(col)));
+                        col += 1;
+                    }
+                }
+                ';' => {
+                    it.next();
+                    if it.next_if_eq(&';').is_some() {
+                        tokens.push(Token::Symbol(Sym::DSEMI, Col(col)));
+                        col += 2;
+                    } else {
+                        tokens.push(Token::Symbol(Sym::SEMICOLON, Col(col)));
+                        col += 1;
+                    }
+                }
+                '<' => {
+                    it.next();
+                    if it.next_if_eq(&'<').is_some() {
+                        if it.next_if_eq(&'<').is_some() {
+                            tokens.push(Token::Symbol(Sym::TLESS, Col(col)));
+                            col += 3;
+                        } else {
+                            let strip_tabs = it.next_if_eq(&'-').is_some();
+                            let sym = if strip_tabs {
+                                Sym::DLESSDASH
+                            } else {
+                                Sym::DLESS
+                            };
+                            tokens.push(Token::Symbol(sym, Col(col)));
+                            col += if strip_tabs { 3 } else { 2 };
+
+                            while it.next_if(|c| *c == ' ' || *c == '\t').is_some() {
+                                col += 1;
+                            }
+                            if it.peek().is_some_and(|c| !is_word_boundary(*c)) {
+                                pending_heredocs.push((tokens.len(), strip_tabs));
+                            }
+                        }
+                    } else if it.next_if_eq(&'&').is_some() {
+                        tokens.push(Token::Symbol(Sym::LESSAND, Col(col)));
+                        col += 2;
+                    } else {
+                        tokens.push(Token::Symbol(Sym::LESS, Col(col)));
+                        col += 1;
+                    }
+                }
+                '>' => {
+                    it.next();
+                    if it.next_if_eq(&'>').is_some() {
+                        tokens.push(Token::Symbol(Sym::DGREAT, Col(col)));
+                        col += 2;
+                    } else if it.next_if_eq(&'&').is_some() {
+                        tokens.push(Token::Symbol(Sym::GREATAND, Col(col)));
+                        col += 2;
+                    } else {
+                        tokens.push(Token::Symbol(Sym::GREAT, Col(col)));
+                        col += 1;
+                    }
                 }
-                '"' => {
-                    tokens.push(build_string(&mut it, &mut col, '"'));
+                '(' if it.clone().nth(1) == Some('(') => {
+                    tokens.push(build_arith(&mut it, &mut col)?);
                 }
-                '\'' => {
-                    tokens.push(build_string(&mut it, &mut col, '\''));
+                '(' | ')' => {
+                    let sym = if c == '(' { Sym::LPAREN } else { Sym::RPAREN };
+                    tokens.push(Token::Symbol(sym, Col(col)));
+                    it.next();
+                    col += 1;
                 }
                 _ => {
-                    tokens.push(make_word(&mut it, &mut col));
+                    let word = make_word(&mut it, &mut col)?;
+                    match word {
+                        Token::Word(ref w, start)
+                            if matches!(it.peek(), Some('<' | '>'))
+                                && !w.is_empty()
+                                && w.chars().all(|c| c.is_ascii_digit()) =>
+                        {
+                            match w.parse() {
+                                Ok(fd) => tokens.push(Token::IoNumber(fd, start)),
+                                Err(_) => tokens.push(word),
+                            }
+                        }
+                        _ => tokens.push(word),
+                    }
                 }
             }
         }
 
+        if let Some((index, _)) = pending_heredocs.first() {
+            if let Token::Word(word, start) = &tokens[*index] {
+                let (delimiter, _) = heredoc_delimiter(word);
+                return Err(CmdParsingError::UnterminatedHereDoc(delimiter, *start));
+            }
+        }
+
         Ok(tokens)
     }
 }
 
+/// Reserved words ending the lists inside compound commands.
+const TERMINATORS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Whether `token` ends the list of a compound command: a terminating
+/// reserved word, or `;;` in a `case`.
+fn ends_compound_list(token: &Token) -> bool {
+    match token {
+        Token::Word(word, _) => TERMINATORS.contains(&word.as_str()),
+        Token::Symbol(sym, _) => *sym == Sym::DSEMI,
+        _ => false,
+    }
+}
+
+/// The error for finding `token` where it does not belong.
+fn unexpected(token: Option<Token>) -> CmdParsingError {
+    match token {
+        Some(Token::Symbol(sym, col)) => CmdParsingError::UnexpectedToken(sym, col),
+        Some(Token::Word(word, col)) => CmdParsingError::UnexpectedWord(word, col),
+        Some(Token::Arith(expr, col)) => {
+            CmdParsingError::UnexpectedWord(format!("(({expr}))"), col)
+        }
+        Some(Token::IoNumber(fd, col)) => CmdParsingError::UnexpectedWord(fd.to_string(), col),
+        Some(Token::HereDoc(..)) => unreachable!("here-documents always follow `<<`"),
+        None => CmdParsingError::UnexpectedEnd,
+    }
+}
+
+fn is_assignment(word: &str) -> Option<(String, String)> {
+    let (name, value) = word.split_once('=')?;
+    if is_valid_name(name) {
+        Some((name.to_string(), value.to_string()))
+    } else {
+        None
+    }
+}
+
 impl CmdParser {
-    pub fn parse(line: &str) -> Result<Vec<Cmd>, CmdParsingError> {
-        let cmds = vec![];
-        let _tokens = Token::tokenize(line)?;
+    pub fn parse(line: &str) -> Result<CmdList, CmdParsingError> {
+        let tokens = Token::tokenize(line)?;
+        let mut it = tokens.into_iter().peekable();
+        let mut cmds = vec![];
+
+        loop {
+            Self::skip_separators(&mut it);
+            if it.peek().is_none() {
+                break;
+            }
+
+            let mut list = Self::parse_and_or(&mut it)?;
+            match it.next() {
+                Some(Token::Symbol(Sym::AMPERSAND, _)) => list.background = true,
+                Some(Token::Symbol(Sym::SEMICOLON | Sym::NEWLINE, _)) | None => {}
+                token => return Err(unexpected(token)),
+            }
+            cmds.push(list);
+        }
 
         Ok(cmds)
     }
-}
 
-impl Cmd {
-    pub fn new(raw_cmd: &str) -> Result<Self, ()> {
-        let mut split = raw_cmd.split(' ');
-        let mut vars: HashMap<String, String> = HashMap::new();
-        let mut name = String::new();
-        let mut state: ParsingState = ParsingState::Vars;
-        let mut args: Vec<String> = vec![];
-
-        while let Some(part) = split.next() {
-            match state {
-                ParsingState::Vars => {
-                    if part.contains('=') {
-                        if let Some((var, value)) = part.split_once('=') {
-                            vars.insert(var.into(), value.into());
-                        } else {
-                            // Error occured while parsing the args
+    fn skip_separators(it: &mut Peekable<std::vec::IntoIter<Token>>) {
+        while it
+            .next_if(|t| matches!(t, Token::Symbol(Sym::NEWLINE | Sym::SEMICOLON, _)))
+            .is_some()
+        {}
+    }
+
+    fn skip_newlines(it: &mut Peekable<std::vec::IntoIter<Token>>) {
+        while it
+            .next_if(|t| matches!(t, Token::Symbol(Sym::NEWLINE, _)))
+            .is_some()
+        {}
+    }
+
+    fn parse_and_or(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<AndOrList, CmdParsingError> {
+        let first = Self::parse_pipeline(it)?;
+        let mut rest = vec![];
+
+        loop {
+            let connector = match it.peek() {
+                Some(Token::Symbol(Sym::AND, _)) => Connector::And,
+                Some(Token::Symbol(Sym::OR, _)) => Connector::Or,
+                _ => break,
+            };
+            it.next();
+            Self::skip_newlines(it);
+            rest.push((connector, Self::parse_pipeline(it)?));
+        }
+
+        Ok(AndOrList {
+            first,
+            rest,
+            background: false,
+        })
+    }
+
+    fn parse_pipeline(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<Pipeline, CmdParsingError> {
+        let mut cmds = vec![Self::parse_command(it)?];
+
+        while it
+            .next_if(|t| matches!(t, Token::Symbol(Sym::PIPE, _)))
+            .is_some()
+        {
+            Self::skip_newlines(it);
+            cmds.push(Self::parse_command(it)?);
+        }
+
+        Ok(Pipeline { cmds })
+    }
+
+    fn parse_command(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<Command, CmdParsingError> {
+        let compound = match it.peek() {
+            Some(Token::Arith(..)) => {
+                let Some(Token::Arith(expr, Col(col))) = it.next() else {
+                    unreachable!()
+                };
+                Compound::Arith(expr, Col(col + 2))
+            }
+            Some(Token::Word(word, _)) => match word.as_str() {
+                "{" => Self::parse_group(it)?,
+                "if" => Self::parse_if(it)?,
+                "while" | "until" => Self::parse_while(it)?,
+                "for" => Self::parse_for(it)?,
+                "case" => Self::parse_case(it)?,
+                "function" => {
+                    it.next();
+                    let name = match it.next() {
+                        Some(Token::Word(name, _)) => name,
+                        token => return Err(unexpected(token)),
+                    };
+                    if it
+                        .next_if(|t| matches!(t, Token::Symbol(Sym::LPAREN, _)))
+                        .is_some()
+                    {
+                        Self::expect_symbol(it, Sym::RPAREN)?;
+                    }
+                    return Self::parse_function_body(it, name);
+                }
+                word if TERMINATORS.contains(&word) => return Err(unexpected(it.next())),
+                _ => return Self::parse_simple_or_function(it),
+            },
+            _ => return Self::parse_simple_or_function(it),
+        };
+
+        let mut redirects = vec![];
+        while let Some(redirect) = Self::parse_next_redirect(it)? {
+            redirects.extend(redirect);
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    /// A simple command, or the `name()` starting a function definition.
+    fn parse_simple_or_function(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<Command, CmdParsingError> {
+        let mut cmd = Self::parse_simple_cmd(it)?;
+        let is_name =
+            cmd.words.len() == 1 && cmd.assignments.is_empty() && cmd.redirects.is_empty();
+        if !is_name
+            || it
+                .next_if(|t| matches!(t, Token::Symbol(Sym::LPAREN, _)))
+                .is_none()
+        {
+            return Ok(Command::Simple(cmd));
+        }
+
+        Self::expect_symbol(it, Sym::RPAREN)?;
+        Self::parse_function_body(it, cmd.words.remove(0))
+    }
+
+    /// The compound command making up the body of function `name`.
+    fn parse_function_body(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+        name: String,
+    ) -> Result<Command, CmdParsingError> {
+        Self::skip_newlines(it);
+        let is_compound = match it.peek() {
+            Some(Token::Word(word, _)) => {
+                matches!(
+                    word.as_str(),
+                    "{" | "if" | "while" | "until" | "for" | "case"
+                )
+            }
+            Some(Token::Arith(..)) => true,
+            _ => false,
+        };
+        if !is_compound {
+            return Err(unexpected(it.next()));
+        }
+
+        Ok(Command::Function(name, Rc::new(Self::parse_command(it)?)))
+    }
+
+    /// `{ list; }`
+    fn parse_group(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<Compound, CmdParsingError> {
+        it.next();
+        let list = Self::parse_nonempty_list(it)?;
+        Self::expect_word(it, "}")?;
+        Ok(Compound::Group(list))
+    }
+
+    /// Parses the commands of a compound command up to the reserved word or
+    /// `;;` ending them, which is left for the caller to check.
+    fn parse_compound_list(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<CmdList, CmdParsingError> {
+        let mut cmds = vec![];
+
+        loop {
+            Self::skip_separators(it);
+            match it.peek() {
+                Some(token) if ends_compound_list(token) => return Ok(cmds),
+                None => return Err(CmdParsingError::UnexpectedEnd),
+                _ => {}
+            }
+
+            let mut list = Self::parse_and_or(it)?;
+            match it.peek() {
+                Some(Token::Symbol(Sym::AMPERSAND, _)) => {
+                    it.next();
+                    list.background = true;
+                }
+                Some(Token::Symbol(Sym::SEMICOLON | Sym::NEWLINE, _)) => {
+                    it.next();
+                }
+                Some(token) if ends_compound_list(token) => {}
+                _ => return Err(unexpected(it.next())),
+            }
+            cmds.push(list);
+        }
+    }
+
+    /// Like [`Self::parse_compound_list`], for the lists that cannot be
+    /// empty.
+    fn parse_nonempty_list(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<CmdList, CmdParsingError> {
+        let cmds = Self::parse_compound_list(it)?;
+        if cmds.is_empty() {
+            return Err(unexpected(it.next()));
+        }
+        Ok(cmds)
+    }
+
+    fn expect_word(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+        expected: &str,
+    ) -> Result<(), CmdParsingError> {
+        match it.next() {
+            Some(Token::Word(word, _)) if word == expected => Ok(()),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn expect_symbol(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+        expected: Sym,
+    ) -> Result<(), CmdParsingError> {
+        match it.next() {
+            Some(Token::Symbol(sym, _)) if sym == expected => Ok(()),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    /// `do list; done`
+    fn parse_do_group(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<CmdList, CmdParsingError> {
+        Self::expect_word(it, "do")?;
+        let body = Self::parse_nonempty_list(it)?;
+        Self::expect_word(it, "done")?;
+        Ok(body)
+    }
+
+    fn parse_if(it: &mut Peekable<std::vec::IntoIter<Token>>) -> Result<Compound, CmdParsingError> {
+        it.next();
+        let mut branches = vec![];
+
+        loop {
+            let condition = Self::parse_nonempty_list(it)?;
+            Self::expect_word(it, "then")?;
+            branches.push((condition, Self::parse_nonempty_list(it)?));
+
+            match it.next() {
+                Some(Token::Word(word, _)) if word == "elif" => {}
+                Some(Token::Word(word, _)) if word == "else" => {
+                    let otherwise = Self::parse_nonempty_list(it)?;
+                    Self::expect_word(it, "fi")?;
+                    return Ok(Compound::If {
+                        branches,
+                        otherwise: Some(otherwise),
+                    });
+                }
+                Some(Token::Word(word, _)) if word == "fi" => {
+                    return Ok(Compound::If {
+                        branches,
+                        otherwise: None,
+                    })
+                }
+                token => return Err(unexpected(token)),
+            }
+        }
+    }
+
+    fn parse_while(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<Compound, CmdParsingError> {
+        let until = matches!(it.next(), Some(Token::Word(word, _)) if word == "until");
+        let condition = Self::parse_nonempty_list(it)?;
+        let body = Self::parse_do_group(it)?;
+
+        Ok(Compound::While {
+            until,
+            condition,
+            body,
+        })
+    }
+
+    fn parse_for(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<Compound, CmdParsingError> {
+        it.next();
+        let var = match it.next() {
+            Some(Token::Word(var, _)) if is_valid_name(&var) => var,
+            Some(Token::Arith(expr, col)) => {
+                let [init, condition, step] = expr.split(';').collect::<Vec<_>>()[..] else {
+                    return Err(CmdParsingError::UnexpectedWord(format!("(({expr}))"), col));
+                };
+                let (init, condition, step) = (init.into(), condition.into(), step.into());
+                Self::skip_separators(it);
+                return Ok(Compound::ArithFor {
+                    init,
+                    condition,
+                    step,
+                    body: Self::parse_do_group(it)?,
+                });
+            }
+            token => return Err(unexpected(token)),
+        };
+
+        Self::skip_newlines(it);
+        let words = match it.peek() {
+            Some(Token::Word(word, _)) if word == "in" => {
+                it.next();
+                let mut words = vec![];
+                while let Some(Token::Word(word, _)) = it.next_if(|t| matches!(t, Token::Word(..)))
+                {
+                    words.push(word);
+                }
+                match it.next() {
+                    Some(Token::Symbol(Sym::SEMICOLON | Sym::NEWLINE, _)) => {}
+                    token => return Err(unexpected(token)),
+                }
+                Some(words)
+            }
+            _ => None,
+        };
+        Self::skip_separators(it);
+
+        Ok(Compound::For {
+            var,
+            words,
+            body: Self::parse_do_group(it)?,
+        })
+    }
+
+    fn parse_case(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<Compound, CmdParsingError> {
+        it.next();
+        let word = match it.next() {
+            Some(Token::Word(word, _)) => word,
+            token => return Err(unexpected(token)),
+        };
+        Self::skip_newlines(it);
+        Self::expect_word(it, "in")?;
+
+        let mut arms = vec![];
+        loop {
+            Self::skip_newlines(it);
+            if it
+                .next_if(|t| matches!(t, Token::Word(word, _) if word == "esac"))
+                .is_some()
+            {
+                break;
+            }
+
+            it.next_if(|t| matches!(t, Token::Symbol(Sym::LPAREN, _)));
+            let mut patterns = vec![];
+            loop {
+                match it.next() {
+                    Some(Token::Word(pattern, _)) => patterns.push(pattern),
+                    token => return Err(unexpected(token)),
+                }
+                match it.next() {
+                    Some(Token::Symbol(Sym::PIPE, _)) => {}
+                    Some(Token::Symbol(Sym::RPAREN, _)) => break,
+                    token => return Err(unexpected(token)),
+                }
+            }
+            arms.push((patterns, Self::parse_compound_list(it)?));
+
+            match it.next() {
+                Some(Token::Symbol(Sym::DSEMI, _)) => {}
+                Some(Token::Word(word, _)) if word == "esac" => break,
+                token => return Err(unexpected(token)),
+            }
+        }
+
+        Ok(Compound::Case { word, arms })
+    }
+
+    fn parse_simple_cmd(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<SimpleCmd, CmdParsingError> {
+        let mut cmd = SimpleCmd::default();
+
+        loop {
+            match it.peek() {
+                Some(Token::Word(_, _)) => {
+                    let Some(Token::Word(word, col)) = it.next() else {
+                        unreachable!()
+                    };
+                    if cmd.words.is_empty() {
+                        if let Some(assignment) = is_assignment(&word) {
+                            cmd.assignments.push(assignment);
+                            continue;
                         }
-                    } else {
-                        name = part.into();
-                        state = ParsingState::Args;
                     }
+                    cmd.words.push(word);
+                    cmd.word_cols.push(col);
                 }
-                ParsingState::Args => {
-                    // TODO: Expand "*" "~" and "$VARNAME"
-                    // Expntion to $(echo "lol")
-                    // bash extensions, etc, ..
-                    let mut temp_args = vec![expand_vars_into_arg(&vars, &string_match(part))];
+                _ => match Self::parse_next_redirect(it)? {
+                    Some(redirects) => cmd.redirects.extend(redirects),
+                    None => break,
+                },
+            }
+        }
+
+        if cmd.assignments.is_empty() && cmd.words.is_empty() && cmd.redirects.is_empty() {
+            return match it.next() {
+                Some(Token::Symbol(sym, col)) => Err(CmdParsingError::UnexpectedToken(sym, col)),
+                _ => Err(CmdParsingError::UnexpectedEnd),
+            };
+        }
+
+        Ok(cmd)
+    }
+
+    /// Parses the redirection starting at the next token, if it starts one.
+    fn parse_next_redirect(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+    ) -> Result<Option<Vec<Redirect>>, CmdParsingError> {
+        match it.peek() {
+            Some(Token::IoNumber(fd, _)) => {
+                let fd = *fd;
+                it.next();
+                let Some(Token::Symbol(sym, col)) = it.next() else {
+                    unreachable!("io numbers are always followed by a redirection")
+                };
+                Self::parse_redirect(it, Some(fd), sym, col).map(Some)
+            }
+            Some(Token::Symbol(
+                sym @ (Sym::LESS
+                | Sym::GREAT
+                | Sym::DGREAT
+                | Sym::LESSAND
+                | Sym::GREATAND
+                | Sym::ANDGREAT
+                | Sym::ANDDGREAT
+                | Sym::DLESS
+                | Sym::DLESSDASH
+                | Sym::TLESS),
+                col,
+            )) => {
+                let (sym, col) = (*sym, *col);
+                it.next();
+                Self::parse_redirect(it, None, sym, col).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_redirect(
+        it: &mut Peekable<std::vec::IntoIter<Token>>,
+        fd: Option<i32>,
+        sym: Sym,
+        col: Col,
+    ) -> Result<Vec<Redirect>, CmdParsingError> {
+        let target = match it.next() {
+            Some(Token::HereDoc(body, expand, _)) => {
+                return Ok(vec![Redirect {
+                    fd: fd.unwrap_or(0),
+                    kind: RedirectKind::HereDoc { expand },
+                    target: body,
+                }])
+            }
+            Some(Token::Word(word, _)) => word,
+            Some(Token::Symbol(sym, col)) => {
+                return Err(CmdParsingError::UnexpectedToken(sym, col))
+            }
+            Some(Token::IoNumber(fd, _)) => fd.to_string(),
+            token => return Err(unexpected(token)),
+        };
+
+        let (default_fd, kind) = match sym {
+            Sym::LESS => (0, RedirectKind::Input),
+            Sym::GREAT => (1, RedirectKind::Output),
+            Sym::DGREAT => (1, RedirectKind::Append),
+            Sym::LESSAND => (0, RedirectKind::DupInput),
+            Sym::GREATAND => (1, RedirectKind::DupOutput),
+            Sym::TLESS => (0, RedirectKind::HereString),
+            Sym::ANDGREAT | Sym::ANDDGREAT => {
+                let kind = if sym == Sym::ANDGREAT {
+                    RedirectKind::Output
+                } else {
+                    RedirectKind::Append
+                };
+                return Ok(vec![
+                    Redirect {
+                        fd: 1,
+                        kind,
+                        target,
+                    },
+                    Redirect {
+                        fd: 2,
+                        kind: RedirectKind::DupOutput,
+                        target: "1".into(),
+                    },
+                ]);
+            }
+            _ => return Err(CmdParsingError::UnexpectedToken(sym, col)),
+        };
 
-                    temp_args.extend(
-                        split
-                            .map(|arg| expand_vars_into_arg(&vars, &string_match(arg)))
-                            .collect::<Vec<String>>(),
-                    );
-                    args.extend(temp_args);
-                    break;
+        Ok(vec![Redirect {
+            fd: fd.unwrap_or(default_fd),
+            kind,
+            target,
+        }])
+    }
+}
+
+/// Index right after the quoted section, `\x` escape, `${...}`, `$(...)` or
+/// `` `...` `` starting at `chars[i]`, if there is one there.
+fn skip_protected(chars: &[char], i: usize) -> Option<usize> {
+    let closing = |start: usize, close: char, escapes: bool| {
+        let mut j = start;
+        while j < chars.len() {
+            match chars[j] {
+                '\\' if escapes => j += 1,
+                c if c == close => return j + 1,
+                _ => {}
+            }
+            j += 1;
+        }
+        chars.len()
+    };
+
+    let end = match (chars[i], chars.get(i + 1)) {
+        ('\\', _) => i + 2,
+        ('\'', _) => closing(i + 1, '\'', false),
+        ('"', _) => closing(i + 1, '"', true),
+        ('`', _) => closing(i + 1, '`', true),
+        ('$', Some(&open @ ('{' | '('))) => {
+            let close = if open == '{' { '}' } else { ')' };
+            let mut depth = 0;
+            let mut j = i + 1;
+            while j < chars.len() {
+                if let Some(next) = skip_protected(chars, j) {
+                    j = next;
+                    continue;
+                }
+                if chars[j] == open {
+                    depth += 1;
+                } else if chars[j] == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
                 }
+                j += 1;
             }
+            j + 1
+        }
+        _ => return None,
+    };
+    Some(end.min(chars.len()))
+}
+
+/// Finds the first `{...}` group from `from` on, returning the indexes of its
+/// braces and of its top-level commas.
+fn find_brace_group(chars: &[char], from: usize) -> Option<(usize, usize, Vec<usize>)> {
+    let mut i = from;
+    while i < chars.len() {
+        if let Some(next) = skip_protected(chars, i) {
+            i = next;
+            continue;
+        }
+        if chars[i] != '{' {
+            i += 1;
+            continue;
         }
 
-        Ok(Self {
+        let open = i;
+        let mut depth = 0;
+        let mut commas = vec![];
+        while i < chars.len() {
+            if let Some(next) = skip_protected(chars, i) {
+                i = next;
+                continue;
+            }
+            match chars[i] {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((open, i, commas));
+                    }
+                }
+                ',' if depth == 1 => commas.push(i),
+                _ => {}
+            }
+            i += 1;
+        }
+        // Unclosed, the `{` stands for itself.
+        i = open + 1;
+    }
+    None
+}
+
+/// Most words brace expansion may turn a word into, larger expansions leave
+/// it as it is rather than exhausting memory.
+const MAX_BRACE_WORDS: usize = 1 << 16;
+
+/// The sequence a `{x..y[..step]}` stands for, numbers or single characters.
+fn brace_range(text: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = text.split("..").collect();
+    let (start, end) = match parts[..] {
+        [start, end] | [start, end, _] => (start, end),
+        _ => return None,
+    };
+    let step = match parts.get(2) {
+        Some(step) => step.parse::<i64>().ok()?.unsigned_abs().max(1),
+        None => 1,
+    };
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        if first.abs_diff(last) / step >= MAX_BRACE_WORDS as u64 {
+            return None;
+        }
+        let padded = |s: &str| s.trim_start_matches('-').starts_with('0') && s.len() > 1;
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        let values = if first <= last {
+            (first..=last).step_by(step as usize).collect::<Vec<_>>()
+        } else {
+            (last..=first).rev().step_by(step as usize).collect()
+        };
+        return Some(values.iter().map(|n| format!("{n:0width$}")).collect());
+    }
+
+    let mut start_chars = start.chars();
+    let mut end_chars = end.chars();
+    match (
+        start_chars.next(),
+        start_chars.next(),
+        end_chars.next(),
+        end_chars.next(),
+    ) {
+        (Some(first), None, Some(last), None)
+            if first.is_ascii_alphabetic() && last.is_ascii_alphabetic() =>
+        {
+            let (first, last) = (first as u8, last as u8);
+            let values = if first <= last {
+                (first..=last).step_by(step as usize).collect::<Vec<_>>()
+            } else {
+                (last..=first).rev().step_by(step as usize).collect()
+            };
+            Some(values.iter().map(|c| (*c as char).to_string()).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Brace expansion of a raw word: `a{b,c}d` gives `abd` and `acd`, `{1..3}`
+/// gives `1`, `2` and `3`. Quoted braces and ones without a comma or a valid
+/// range are left alone, and so is a word that would give more than
+/// `MAX_BRACE_WORDS` words.
+fn expand_braces(word: &str) -> Vec<String> {
+    let mut words = vec![];
+    match brace_words(word, &mut words) {
+        true => words,
+        false => vec![word.to_string()],
+    }
+}
+
+/// Adds the brace expansion of `word` to `words`, stopping with `false` once
+/// they are too many.
+fn brace_words(word: &str, words: &mut Vec<String>) -> bool {
+    let chars: Vec<char> = word.chars().collect();
+    let mut from = 0;
+    while let Some((open, close, commas)) = find_brace_group(&chars, from) {
+        let text = |start: usize, end: usize| chars[start..end].iter().collect::<String>();
+        let alternatives = if !commas.is_empty() {
+            let mut bounds = vec![open];
+            bounds.extend(&commas);
+            bounds.push(close);
+            bounds.windows(2).map(|w| text(w[0] + 1, w[1])).collect()
+        } else if let Some(range) = brace_range(&text(open + 1, close)) {
+            range
+        } else {
+            from = open + 1;
+            continue;
+        };
+
+        let (prefix, suffix) = (text(0, open), text(close + 1, chars.len()));
+        return alternatives
+            .iter()
+            .all(|alternative| brace_words(&format!("{prefix}{alternative}{suffix}"), words));
+    }
+
+    words.push(word.to_string());
+    words.len() <= MAX_BRACE_WORDS
+}
+
+/// Expands raw words into fields, braces first, with `vars` overriding shell
+/// variables.
+pub fn expand_words(
+    executor: &mut Executor,
+    vars: &HashMap<String, String>,
+    words: &[String],
+) -> Result<Vec<String>, CommandError> {
+    let mut fields = vec![];
+    for word in words.iter().flat_map(|word| expand_braces(word)) {
+        fields.extend(expand_word(executor, vars, &word)?);
+    }
+    Ok(fields)
+}
+
+impl Redirect {
+    /// Expands the target, or the here-document body, with `vars` overriding
+    /// shell variables.
+    pub fn expand(
+        &self,
+        executor: &mut Executor,
+        vars: &HashMap<String, String>,
+    ) -> Result<Redirect, CommandError> {
+        let target = match self.kind {
+            RedirectKind::HereDoc { expand: false } => self.target.clone(),
+            RedirectKind::HereDoc { expand: true } => expand_heredoc(executor, vars, &self.target)?,
+            RedirectKind::HereString => {
+                format!("{}\n", expand_vars_into_arg(executor, vars, &self.target)?)
+            }
+            _ => expand_vars_into_arg(executor, vars, &self.target)?,
+        };
+        Ok(Redirect {
+            target,
+            ..self.clone()
+        })
+    }
+}
+
+/// The text `word` stands for as an alias not in `used`, with its own first
+/// word expanded as well.
+fn alias_text(
+    word: &str,
+    aliases: &HashMap<String, String>,
+    used: &mut HashSet<String>,
+) -> Option<String> {
+    let value = aliases.get(word)?;
+    if !used.insert(word.to_string()) {
+        return None;
+    }
+
+    let value = value.trim_start();
+    let (first, rest) = value
+        .find(is_word_boundary)
+        .map_or((value, ""), |end| value.split_at(end));
+    Some(match alias_text(first, aliases, used) {
+        Some(first) => format!("{first}{rest}"),
+        None => value.to_string(),
+    })
+}
+
+impl SimpleCmd {
+    /// Replaces the first word by its alias, and the word after an alias
+    /// ending with a blank as well. The first word of an alias is itself
+    /// expanded, but an alias is never expanded twice, which ends recursive
+    /// ones. Returns `None` when there is no alias to expand.
+    pub fn expand_aliases(
+        &self,
+        aliases: &HashMap<String, String>,
+    ) -> Result<Option<Command>, CmdParsingError> {
+        let mut used = HashSet::new();
+        let mut text: Vec<String> = self
+            .assignments
+            .iter()
+            .map(|(var, value)| format!("{var}={value}"))
+            .collect();
+        let mut expanding = true;
+        for word in &self.words {
+            match expanding.then(|| alias_text(word, aliases, &mut used)) {
+                Some(Some(value)) => {
+                    expanding = value.ends_with([' ', '\t']);
+                    text.push(value);
+                }
+                _ => {
+                    text.push(word.clone());
+                    expanding = false;
+                }
+            }
+        }
+        if used.is_empty() {
+            return Ok(None);
+        }
+
+        let mut list = CmdParser::parse(&text.join(" "))?;
+        let redirects = self.redirects.clone();
+        let single = list.len() == 1
+            && list[0].rest.is_empty()
+            && !list[0].background
+            && list[0].first.cmds.len() == 1;
+        let expansion = match list.pop() {
+            Some(and_or) if single => match and_or.first.cmds.into_iter().next() {
+                Some(Command::Simple(mut cmd)) => {
+                    cmd.redirects.extend(redirects);
+                    Command::Simple(cmd)
+                }
+                Some(Command::Compound(compound, mut own)) => {
+                    own.extend(redirects);
+                    Command::Compound(compound, own)
+                }
+                Some(function) => function,
+                None => unreachable!("pipelines have at least one command"),
+            },
+            None => Command::Simple(SimpleCmd {
+                redirects,
+                ..SimpleCmd::default()
+            }),
+            Some(and_or) => {
+                list.push(and_or);
+                Command::Compound(Compound::Group(list), redirects)
+            }
+        };
+
+        Ok(Some(expansion))
+    }
+
+    /// Expands the command against the shell state. Assignments only end up
+    /// in `variables_overrides`, the shell variables themselves are left
+    /// alone.
+    pub fn expand(&self, executor: &mut Executor) -> Result<Cmd, CommandError> {
+        let mut vars: HashMap<String, String> = HashMap::new();
+        for (var, value) in &self.assignments {
+            let value = expand_assignment(executor, &vars, value)?;
+            vars.insert(var.clone(), value);
+        }
+
+        // TODO: bash extensions, etc, ..
+        let mut args = vec![];
+        for (i, word) in self.words.iter().enumerate() {
+            let col = self.word_cols.get(i).map_or(0, |Col(col)| *col);
+            let fields = expand_words(executor, &vars, std::slice::from_ref(word))
+                .map_err(|e| e.offset_column(col))?;
+            args.extend(fields);
+        }
+        let name = if args.is_empty() {
+            String::new()
+        } else {
+            args.remove(0)
+        };
+
+        let redirects = self
+            .redirects
+            .iter()
+            .map(|redirect| redirect.expand(executor, &vars))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Cmd {
             variables_overrides: vars,
             name,
             args,
+            redirects,
         })
     }
 }
+
+#[cfg(test)]
+fn simple(cmd: &Command) -> &SimpleCmd {
+    match cmd {
+        Command::Simple(cmd) => cmd,
+        _ => panic!("not a simple command: {cmd}"),
+    }
+}
+
+#[cfg(test)]
+fn words(cmd: &Command) -> Vec<&str> {
+    simple(cmd).words.iter().map(String::as_str).collect()
+}
+
+#[test]
+fn test_tokenize_quoted_word() {
+    let tokens = Token::tokenize("echo 'a b'\"c\"d|wc").unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Word("echo".into(), Col(0)),
+            Token::Word("'a b'\"c\"d".into(), Col(5)),
+            Token::Symbol(Sym::PIPE, Col(14)),
+            Token::Word("wc".into(), Col(15)),
+        ]
+    );
+}
+
+#[test]
+fn test_tokenize_comments() {
+    let tokens = Token::tokenize("#!/bin/dsh\necho a#b $# '#' # ignored | wc").unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Symbol(Sym::NEWLINE, Col(10)),
+            Token::Word("echo".into(), Col(11)),
+            Token::Word("a#b".into(), Col(16)),
+            Token::Word("$#".into(), Col(20)),
+            Token::Word("'#'".into(), Col(23)),
+        ]
+    );
+}
+
+#[test]
+fn test_tokenize_unterminated_string() {
+    assert!(matches!(
+        Token::tokenize("echo 'oops"),
+        Err(CmdParsingError::UnterminatedString('\'', Col(5)))
+    ));
+}
+
+#[test]
+fn test_tokenize_braced_expansion() {
+    let tokens = Token::tokenize("echo ${A:-x y}${B#'}'}|wc").unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Word("echo".into(), Col(0)),
+            Token::Word("${A:-x y}${B#'}'}".into(), Col(5)),
+            Token::Symbol(Sym::PIPE, Col(22)),
+            Token::Word("wc".into(), Col(23)),
+        ]
+    );
+    assert!(matches!(
+        Token::tokenize("echo ${A:-x"),
+        Err(CmdParsingError::UnterminatedExpansion("${", Col(5)))
+    ));
+}
+
+#[test]
+fn test_parse_lists() {
+    let list = CmdParser::parse("FOO=1 make -j4 && ./run | tee log || notify; sleep 1 &").unwrap();
+
+    assert_eq!(list.len(), 2);
+    assert_eq!(
+        simple(&list[0].first.cmds[0]).assignments,
+        vec![("FOO".to_string(), "1".to_string())]
+    );
+    assert_eq!(words(&list[0].first.cmds[0]), vec!["make", "-j4"]);
+    assert_eq!(list[0].rest.len(), 2);
+    assert_eq!(list[0].rest[0].0, Connector::And);
+    assert_eq!(list[0].rest[0].1.cmds.len(), 2);
+    assert_eq!(list[0].rest[1].0, Connector::Or);
+    assert!(!list[0].background);
+    assert_eq!(words(&list[1].first.cmds[0]), vec!["sleep", "1"]);
+    assert!(list[1].background);
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(matches!(
+        CmdParser::parse("| wc"),
+        Err(CmdParsingError::UnexpectedToken(Sym::PIPE, Col(0)))
+    ));
+    assert!(matches!(
+        CmdParser::parse("ls &&"),
+        Err(CmdParsingError::UnexpectedEnd)
+    ));
+    assert!(CmdParser::parse("  ; ").unwrap().is_empty());
+    assert!(matches!(
+        CmdParser::parse("((x)) y"),
+        Err(CmdParsingError::UnexpectedWord(word, Col(6))) if word == "y"
+    ));
+    assert!(matches!(
+        CmdParser::parse("(( (x) + 1 )"),
+        Err(CmdParsingError::UnterminatedExpansion("((", Col(0)))
+    ));
+}
+
+#[test]
+fn test_parse_redirects() {
+    let list = CmdParser::parse("cmd <in 2>err >>out 3>&1 &>all >&-").unwrap();
+    let cmd = &list[0].first.cmds[0];
+    let redirect = |fd, kind, target: &str| Redirect {
+        fd,
+        kind,
+        target: target.into(),
+    };
+
+    assert_eq!(words(cmd), vec!["cmd"]);
+    assert_eq!(
+        simple(cmd).redirects,
+        vec![
+            redirect(0, RedirectKind::Input, "in"),
+            redirect(2, RedirectKind::Output, "err"),
+            redirect(1, RedirectKind::Append, "out"),
+            redirect(3, RedirectKind::DupOutput, "1"),
+            redirect(1, RedirectKind::Output, "all"),
+            redirect(2, RedirectKind::DupOutput, "1"),
+            redirect(1, RedirectKind::DupOutput, "-"),
+        ]
+    );
+    assert!(matches!(
+        CmdParser::parse("cmd >"),
+        Err(CmdParsingError::UnexpectedEnd)
+    ));
+}
+
+#[test]
+fn test_parse_heredocs() {
+    let list =
+        CmdParser::parse("cat <<EOF; cat <<-'END' <<<$HOME\nhello $USER\nEOF\n\tbye\n\tEND\n")
+            .unwrap();
+    let redirect = |list: &CmdList, i: usize| simple(&list[i].first.cmds[0]).redirects.clone();
+
+    assert_eq!(list.len(), 2);
+    assert_eq!(
+        redirect(&list, 0),
+        vec![Redirect {
+            fd: 0,
+            kind: RedirectKind::HereDoc { expand: true },
+            target: "hello $USER\n".into(),
+        }]
+    );
+    assert_eq!(
+        redirect(&list, 1),
+        vec![
+            Redirect {
+                fd: 0,
+                kind: RedirectKind::HereDoc { expand: false },
+                target: "bye\n".into(),
+            },
+            Redirect {
+                fd: 0,
+                kind: RedirectKind::HereString,
+                target: "$HOME".into(),
+            },
+        ]
+    );
+    assert!(matches!(
+        CmdParser::parse("cat <<EOF\nno end"),
+        Err(CmdParsingError::UnterminatedHereDoc(delimiter, Col(6))) if delimiter == "EOF"
+    ));
+}
+
+#[test]
+fn test_parse_compound() {
+    let list = CmdParser::parse(
+        "if a; then b\nelif c\nthen d; else e; fi | cat\n\
+         for x in 1 2; do while f; do g; done; done >out",
+    )
+    .unwrap();
+
+    assert_eq!(
+        list[0].first.cmds[0],
+        Command::Compound(
+            Compound::If {
+                branches: vec![
+                    (
+                        CmdParser::parse("a").unwrap(),
+                        CmdParser::parse("b").unwrap()
+                    ),
+                    (
+                        CmdParser::parse("c").unwrap(),
+                        CmdParser::parse("d").unwrap()
+                    ),
+                ],
+                otherwise: Some(CmdParser::parse("e").unwrap()),
+            },
+            vec![]
+        )
+    );
+    assert_eq!(words(&list[0].first.cmds[1]), vec!["cat"]);
+    let Command::Compound(Compound::For { var, words, body }, redirects) = &list[1].first.cmds[0]
+    else {
+        panic!("not a for loop: {}", list[1]);
+    };
+    assert_eq!(var, "x");
+    assert_eq!(
+        words.as_deref(),
+        Some(&["1".to_string(), "2".to_string()][..])
+    );
+    assert_eq!(body[0].to_string(), "while f; do g; done");
+    assert_eq!(redirects.len(), 1);
+
+    for incomplete in [
+        "if a; then",
+        "while a\ndo b",
+        "for x in a",
+        "case a in b) c;;",
+    ] {
+        assert!(matches!(
+            CmdParser::parse(incomplete),
+            Err(CmdParsingError::UnexpectedEnd)
+        ));
+    }
+    assert!(matches!(
+        CmdParser::parse("if; then a; fi"),
+        Err(CmdParsingError::UnexpectedWord(word, Col(4))) if word == "then"
+    ));
+    assert!(matches!(
+        CmdParser::parse("true; done"),
+        Err(CmdParsingError::UnexpectedWord(word, Col(6))) if word == "done"
+    ));
+}
+
+#[test]
+fn test_expand_aliases() {
+    let aliases: HashMap<String, String> = [
+        ("ll", "ls -l"),
+        ("ls", "ls -F"),
+        ("sudo", "sudo "),
+        ("loop", "loop again"),
+        ("pager", "cat | less"),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect();
+    let expand = |line: &str| {
+        let list = CmdParser::parse(line).unwrap();
+        simple(&list[0].first.cmds[0])
+            .expand_aliases(&aliases)
+            .unwrap()
+            .map(|cmd| cmd.to_string())
+    };
+
+    assert_eq!(expand("ll -a >out").as_deref(), Some("ls -F -l -a >out"));
+    assert_eq!(expand("X=1 sudo ll").as_deref(), Some("X=1 sudo ls -F -l"));
+    assert_eq!(expand("loop").as_deref(), Some("loop again"));
+    assert_eq!(expand("pager x").as_deref(), Some("{ cat | less x; }"));
+    assert_eq!(expand("'ll'"), None);
+    assert_eq!(expand("echo ll"), None);
+}
+
+#[test]
+fn test_expand_braces() {
+    assert_eq!(expand_braces("a{b,c}d"), ["abd", "acd"]);
+    assert_eq!(expand_braces("{a,b{1,2}}"), ["a", "b1", "b2"]);
+    assert_eq!(
+        expand_braces("{1..3}{x,y}"),
+        ["1x", "1y", "2x", "2y", "3x", "3y"]
+    );
+    assert_eq!(expand_braces("{3..1}"), ["3", "2", "1"]);
+    assert_eq!(expand_braces("{0..20..5}"), ["0", "5", "10", "15", "20"]);
+    assert_eq!(expand_braces("{08..10}"), ["08", "09", "10"]);
+    assert_eq!(expand_braces("{a..e..2}"), ["a", "c", "e"]);
+    assert_eq!(expand_braces("{1..9999999999}"), ["{1..9999999999}"]);
+    assert_eq!(
+        expand_braces("{-9223372036854775808..9223372036854775807}"),
+        ["{-9223372036854775808..9223372036854775807}"]
+    );
+    assert_eq!(expand_braces("{1..9999999999..1000000}").len(), 10000);
+    assert_eq!(
+        expand_braces("{1..60000}{1..60000}"),
+        ["{1..60000}{1..60000}"]
+    );
+    assert_eq!(expand_braces("{a,b}{1..32768}").len(), 65536);
+    assert_eq!(expand_braces("{a,b,c}{1..32768}"), ["{a,b,c}{1..32768}"]);
+    assert_eq!(expand_braces("x{,.bak}"), ["x", "x.bak"]);
+    assert_eq!(expand_braces("{a}{b,c}"), ["{a}b", "{a}c"]);
+    assert_eq!(expand_braces("'{a,b}'"), ["'{a,b}'"]);
+    assert_eq!(expand_braces("\\{a,b}"), ["\\{a,b}"]);
+    assert_eq!(expand_braces("${A:-{a,b}}"), ["${A:-{a,b}}"]);
+    assert_eq!(expand_braces("$(echo {a,b})"), ["$(echo {a,b})"]);
+    assert_eq!(expand_braces("{}"), ["{}"]);
+    assert_eq!(expand_braces("{a,b"), ["{a,b"]);
+    assert_eq!(expand_braces("{a{b,c}"), ["{ab", "{ac"]);
+}
+
+#[test]
+fn test_display_roundtrip() {
+    let line = "FOO=1 make 'a b' 2>&1 >>log | tee out && ./run || notify &";
+    let list = CmdParser::parse(line).unwrap();
+
+    assert_eq!(list[0].to_string(), line);
+
+    let line = "(( (x) * 2 > 1 )) 2>err | cat";
+    let list = CmdParser::parse(line).unwrap();
+
+    assert_eq!(list[0].to_string(), line);
+
+    for line in [
+        "if a; then b; elif c; then d & else e; fi",
+        "until a; do b; done <in",
+        "for x; do a; done",
+        "for ((i = 0; i < 3; i++)); do a; done",
+        "case $x in a|b) c; ;; *) ;; esac",
+        "f() { a; b & }",
+        "g() for x; do a; done >out",
+    ] {
+        let list = CmdParser::parse(line).unwrap();
+        assert_eq!(list[0].to_string(), line);
+    }
+}
@@ -1,7 +1,31 @@
 use std::io;
 
+use crate::cmd::{Col, Sym};
+
 #[derive(Debug)]
-pub enum CmdParsingError {}
+pub enum CmdParsingError {
+    UnterminatedString(char, Col),
+    UnexpectedToken(Sym, Col),
+    UnexpectedEnd,
+    UnterminatedHereDoc(String, Col),
+    /// `${`, `$(` or `((` without its closing bracket.
+    UnterminatedExpansion(&'static str, Col),
+    /// A word where a command must have ended, as in `((x)) y`.
+    UnexpectedWord(String, Col),
+}
+
+impl CmdParsingError {
+    /// Whether more input lines could complete the command.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            CmdParsingError::UnterminatedString(..)
+                | CmdParsingError::UnexpectedEnd
+                | CmdParsingError::UnterminatedHereDoc(..)
+                | CmdParsingError::UnterminatedExpansion(..)
+        )
+    }
+}
 
 impl std::error::Error for CmdParsingError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
@@ -15,7 +39,28 @@ impl std::error::Error for CmdParsingError {
 
 impl std::fmt::Display for CmdParsingError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            CmdParsingError::UnterminatedString(del, Col(col)) => {
+                write!(
+                    f,
+                    "[parse]: unterminated {del} string starting at column {col}"
+                )
+            }
+            CmdParsingError::UnexpectedToken(sym, Col(col)) => {
+                write!(f, "[parse]: unexpected {sym:?} at column {col}")
+            }
+            CmdParsingError::UnexpectedEnd => write!(f, "[parse]: unexpected end of input"),
+            CmdParsingError::UnterminatedHereDoc(delimiter, Col(col)) => write!(
+                f,
+                "[parse]: here-document at column {col} is not terminated by `{delimiter}`"
+            ),
+            CmdParsingError::UnterminatedExpansion(open, Col(col)) => {
+                write!(f, "[parse]: unterminated `{open}` starting at column {col}")
+            }
+            CmdParsingError::UnexpectedWord(word, Col(col)) => {
+                write!(f, "[parse]: unexpected `{word}` at column {col}")
+            }
+        }
     }
 }
 
@@ -29,6 +74,42 @@ pub enum CommandError {
     },
     ChildSpawnError(io::Error, String, i32),
     ChildExit(io::Error, i32),
+    /// A command substitution that does not parse.
+    Parse(CmdParsingError),
+    /// Division by zero, overflow or a malformed `$(( ))`/`(( ))`
+    /// expression, at the given column of the expression.
+    Arithmetic(String, Col),
+}
+
+impl CommandError {
+    /// Exit status the shell should record for this failure.
+    pub fn status(&self) -> i32 {
+        match self {
+            CommandError::IOError(_) => 1,
+            CommandError::Custom { status, .. } => *status,
+            CommandError::ChildSpawnError(_, _, status) => *status,
+            CommandError::ChildExit(_, status) => *status,
+            CommandError::Parse(_) => 2,
+            CommandError::Arithmetic(..) => 1,
+        }
+    }
+
+    /// Moves the column of an arithmetic error by `offset`, for an
+    /// expression that starts that far into the text it was found in.
+    pub fn offset_column(self, offset: u32) -> Self {
+        match self {
+            CommandError::Arithmetic(message, Col(col)) => {
+                CommandError::Arithmetic(message, Col(col + offset))
+            }
+            e => e,
+        }
+    }
+
+    pub fn report(&self) {
+        if !matches!(self, CommandError::ChildExit(_, _)) {
+            eprintln!("{self}");
+        }
+    }
 }
 
 impl std::error::Error for CommandError {
@@ -52,6 +133,10 @@ impl std::fmt::Display for CommandError {
                 write!(f, "[{name}]: Unable to launch this program\nCause: {error}")
             }
             CommandError::ChildExit(_, _) => write!(f, ""),
+            CommandError::Parse(error) => write!(f, "{error}"),
+            CommandError::Arithmetic(message, Col(col)) => {
+                write!(f, "[arithmetic]: {message} at column {col}")
+            }
         }
     }
 }
@@ -0,0 +1,1170 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    io::{self, prelude::*, PipeReader, PipeWriter},
+    os::{fd::AsRawFd, unix::process::ExitStatusExt},
+    path::Path,
+    process::{ExitStatus, Stdio},
+    rc::Rc,
+};
+
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{self, SigHandler, Signal},
+        wait::{waitpid, WaitStatus},
+    },
+    unistd::{dup2, fork, getpgrp, ForkResult, Pid},
+};
+
+use crate::{
+    cmd::{
+        expand_words, AndOrList, Cmd, CmdList, CmdParser, Col, Command, Compound, Connector,
+        Pipeline, Redirect,
+    },
+    error::CommandError,
+    expand::{expand_arithmetic, expand_case_pattern, expand_vars_into_arg, pattern_matches},
+    glob::GlobOptions,
+    history::History,
+    internals::{self, get_internal_functions_map, InternalFuncMap},
+    jobs::{self, Job, JobTable, Process, ProcessGroup, ProcessState},
+    vars::Variables,
+};
+
+pub fn status_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}
+
+fn exit_code(result: Result<ExitStatus, CommandError>) -> i32 {
+    match result {
+        Ok(status) => status_code(status),
+        Err(e) => {
+            e.report();
+            e.status()
+        }
+    }
+}
+
+/// A pipeline stage ready to start. Compound commands, and function
+/// definitions, are only expanded as they run.
+enum Stage<'a> {
+    Cmd(Cmd),
+    /// Owned when it comes from an alias.
+    Compound(Cow<'a, Command>),
+}
+
+/// A `break`, `continue`, `return` or `exit` on its way out of the commands
+/// it skips. The count is how many loops are left to go through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump {
+    Break(usize),
+    Continue(usize),
+    Return,
+    Exit,
+}
+
+pub struct Executor {
+    pub internals: InternalFuncMap,
+    /// Shell functions by name, looked up before builtins.
+    pub functions: HashMap<String, Rc<Command>>,
+    pub aliases: HashMap<String, String>,
+    pub last_status: i32,
+    pub jobs: JobTable,
+    pub vars: Variables,
+    /// `$0` and the positional parameters `$1`, `$2`...
+    pub arg0: String,
+    pub positional: Vec<String>,
+    /// `$$`, which subshells inherit rather than replace.
+    pub pid: u32,
+    /// `$!`, the last process started in the background.
+    pub last_background: Option<Pid>,
+    /// Status of the last command substitution of the command being
+    /// expanded, which is also the status of a command of assignments only.
+    pub last_substitution: Option<i32>,
+    pub glob: GlobOptions,
+    /// Whether jobs get their own process group and the terminal. Only
+    /// enabled for interactive shells.
+    pub job_control: bool,
+    /// How many loops the command being run is nested in.
+    pub loop_depth: usize,
+    /// Set by `break`, `continue` and `return`, commands are skipped until
+    /// the loop or function it targets consumes it.
+    pub jump: Option<Jump>,
+    /// How many function calls the command being run is nested in.
+    pub function_depth: usize,
+    /// How many `source`d scripts the command being run is nested in.
+    pub source_depth: usize,
+    /// Lines entered interactively, for the line editor and `history`.
+    pub history: History,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self {
+            internals: get_internal_functions_map(),
+            functions: HashMap::new(),
+            aliases: HashMap::new(),
+            last_status: 0,
+            jobs: JobTable::new(),
+            vars: Variables::from_env(),
+            arg0: "dsh".into(),
+            positional: vec![],
+            pid: std::process::id(),
+            last_background: None,
+            last_substitution: None,
+            glob: GlobOptions::default(),
+            job_control: false,
+            loop_depth: 0,
+            jump: None,
+            function_depth: 0,
+            source_depth: 0,
+            history: History::new(),
+        }
+    }
+
+    /// Runs `list` in a subshell and returns what it wrote to stdout, without
+    /// its trailing newlines, along with its status.
+    pub fn capture(&mut self, list: &CmdList) -> Result<(String, i32), CommandError> {
+        let (mut reader, writer) = io::pipe().map_err(CommandError::IOError)?;
+        let pid = self.fork_subshell(None, None, Some(writer), |shell| shell.execute(list))?;
+
+        let mut output = vec![];
+        let read = reader.read_to_end(&mut output);
+        let status = loop {
+            match waitpid(pid, None) {
+                Ok(WaitStatus::Exited(_, code)) => break code,
+                Ok(WaitStatus::Signaled(_, sig, _)) => break 128 + sig as i32,
+                Ok(_) | Err(Errno::EINTR) => continue,
+                Err(e) => return Err(CommandError::IOError(e.into())),
+            }
+        };
+        read.map_err(CommandError::IOError)?;
+
+        let output = String::from_utf8_lossy(&output);
+        Ok((output.trim_end_matches('\n').to_string(), status))
+    }
+
+    /// Puts the shell in its own process group in the foreground of the
+    /// terminal so that jobs can be moved in and out of it.
+    pub fn enable_job_control(&mut self) {
+        unsafe {
+            libc::setpgid(0, 0);
+        }
+        jobs::give_terminal(getpgrp().as_raw());
+        self.job_control = true;
+    }
+
+    /// Runs every list of the line in order and returns the status of the
+    /// last command that ran.
+    pub fn execute(&mut self, list: &CmdList) -> i32 {
+        for and_or in list {
+            if self.jump.is_some() {
+                break;
+            }
+            self.last_status = if and_or.background {
+                self.run_background(and_or)
+            } else {
+                self.run_and_or(and_or)
+            };
+        }
+
+        self.last_status
+    }
+
+    /// Runs a whole script, each command as soon as it is complete so that
+    /// the commands before a syntax error still run. Returns the status of
+    /// the last command, or 2 after a syntax error, which ends the script.
+    pub fn run_script(&mut self, source: &str) -> i32 {
+        self.run_lines(source.split_inclusive('\n').map(String::from))
+    }
+
+    /// Runs a script read a line at a time, each line with its newline, like
+    /// `run_script`. A line is only asked for once the commands before it ran.
+    pub fn run_lines(&mut self, lines: impl IntoIterator<Item = String>) -> i32 {
+        let mut pending = String::new();
+        let mut line_number = 0;
+        for line in lines {
+            line_number += 1;
+            pending.push_str(&line);
+            match CmdParser::parse(&pending) {
+                Err(e) if e.is_incomplete() => continue,
+                Ok(list) => {
+                    self.execute(&list);
+                }
+                Err(e) => {
+                    eprintln!("[dsh]: line {line_number}: {e}");
+                    self.last_status = 2;
+                    return self.last_status;
+                }
+            }
+            pending.clear();
+            if self.jump.is_some() {
+                return self.last_status;
+            }
+        }
+
+        if let Err(e) = CmdParser::parse(&pending) {
+            eprintln!("[dsh]: line {line_number}: {e}");
+            self.last_status = 2;
+        }
+        self.last_status
+    }
+
+    /// Runs the startup script at `path`, if there is one.
+    pub fn run_startup_file(&mut self, path: &Path) {
+        match fs::read_to_string(path) {
+            Ok(source) => {
+                self.run_script(&source);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("[dsh]: {}: {e}", path.display()),
+        }
+    }
+
+    /// Prints the background jobs that finished since the last call.
+    pub fn notify_jobs(&mut self) {
+        for line in self.jobs.reap() {
+            eprintln!("{line}");
+        }
+    }
+
+    /// Runs `a && b || c` left to right, skipping a pipeline whenever the
+    /// status so far already decides the outcome of its connector.
+    fn run_and_or(&mut self, and_or: &AndOrList) -> i32 {
+        self.last_status = self.run_pipeline(&and_or.first);
+        for (connector, pipeline) in &and_or.rest {
+            if self.jump.is_some() {
+                break;
+            }
+            let should_run = match connector {
+                Connector::And => self.last_status == 0,
+                Connector::Or => self.last_status != 0,
+            };
+            if should_run {
+                self.last_status = self.run_pipeline(pipeline);
+            }
+        }
+
+        self.last_status
+    }
+
+    fn run_background(&mut self, and_or: &AndOrList) -> i32 {
+        let job = if and_or.rest.is_empty() {
+            let stages = match self.expand_pipeline(&and_or.first) {
+                Ok(stages) => stages,
+                Err(e) => return exit_code(Err(e)),
+            };
+            self.launch(stages, and_or.to_string(), false)
+        } else {
+            let group = self.job_control.then_some(ProcessGroup {
+                pgid: 0,
+                foreground: false,
+            });
+            let state = |pid| Process {
+                pid: Some(pid),
+                state: ProcessState::Running,
+            };
+            match self.fork_subshell(group, None, None, |shell| shell.run_and_or(and_or)) {
+                Ok(pid) => Job::new(pid, vec![state(pid)], and_or.to_string()),
+                Err(e) => return exit_code(Err(e)),
+            }
+        };
+
+        let pgid = job.pgid;
+        self.last_background = job.processes.last().and_then(|p| p.pid);
+        let id = self.jobs.insert(job);
+        if self.job_control {
+            eprintln!("[{id}] {pgid}");
+        }
+
+        0
+    }
+
+    fn expand_pipeline<'a>(
+        &mut self,
+        pipeline: &'a Pipeline,
+    ) -> Result<Vec<Stage<'a>>, CommandError> {
+        self.last_substitution = None;
+        pipeline
+            .cmds
+            .iter()
+            .map(|cmd| {
+                let cmd = match cmd {
+                    Command::Simple(simple) => simple
+                        .expand_aliases(&self.aliases)
+                        .map_err(CommandError::Parse)?
+                        .map_or(Cow::Borrowed(cmd), Cow::Owned),
+                    _ => Cow::Borrowed(cmd),
+                };
+                match &*cmd {
+                    Command::Simple(simple) => simple.expand(self).map(Stage::Cmd),
+                    _ => Ok(Stage::Compound(cmd)),
+                }
+            })
+            .collect()
+    }
+
+    fn run_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+        let mut stages = match self.expand_pipeline(pipeline) {
+            Ok(stages) => stages,
+            Err(e) => return exit_code(Err(e)),
+        };
+        // Builtins, functions and compound commands on their own run in the
+        // shell itself so that they can change its state.
+        if stages.len() == 1 {
+            match stages.remove(0) {
+                Stage::Cmd(cmd) if self.is_builtin(&cmd) => return self.run_cmd(cmd),
+                Stage::Compound(command) => return self.run_command(&command),
+                stage => stages.push(stage),
+            }
+        }
+
+        let job = self.launch(stages, pipeline.to_string(), true);
+        self.wait_foreground(job, false)
+    }
+
+    /// Whether `cmd` runs inside the shell rather than as a program.
+    fn is_builtin(&self, cmd: &Cmd) -> bool {
+        cmd.name.is_empty()
+            || self.functions.contains_key(&cmd.name)
+            || self.internals.contains_key(&cmd.name)
+    }
+
+    /// Starts every stage of a pipeline, each one's stdout feeding the next
+    /// one's stdin, in a single process group when job control is on.
+    fn launch(&mut self, stages: Vec<Stage<'_>>, command: String, foreground: bool) -> Job {
+        let count = stages.len();
+        let mut processes = vec![];
+        let mut pgid = 0;
+        let mut stdin: Option<PipeReader> = None;
+
+        for (i, stage) in stages.into_iter().enumerate() {
+            let (reader, writer) = if i + 1 < count {
+                match io::pipe() {
+                    Ok((reader, writer)) => (Some(reader), Some(writer)),
+                    Err(e) => {
+                        let status = exit_code(Err(CommandError::IOError(e)));
+                        processes.push(Process {
+                            pid: None,
+                            state: ProcessState::Done(status),
+                        });
+                        break;
+                    }
+                }
+            } else {
+                (None, None)
+            };
+
+            let group = self
+                .job_control
+                .then_some(ProcessGroup { pgid, foreground });
+            match self.spawn_stage(stage, stdin.take(), writer, group) {
+                Ok(pid) => {
+                    if pgid == 0 {
+                        pgid = pid.as_raw();
+                    }
+                    if self.job_control {
+                        // Also done by the child; whichever runs first wins.
+                        unsafe {
+                            libc::setpgid(pid.as_raw(), pgid);
+                        }
+                    }
+                    processes.push(Process {
+                        pid: Some(pid),
+                        state: ProcessState::Running,
+                    });
+                }
+                Err(e) => processes.push(Process {
+                    pid: None,
+                    state: ProcessState::Done(exit_code(Err(e))),
+                }),
+            }
+            stdin = reader;
+        }
+
+        Job::new(Pid::from_raw(pgid), processes, command)
+    }
+
+    /// Waits for `job` while it owns the terminal, optionally waking it up
+    /// first. A job that gets stopped is kept in the job table.
+    pub fn wait_foreground(&mut self, mut job: Job, resume: bool) -> i32 {
+        let owns_terminal = self.job_control && job.pgid.as_raw() != 0;
+        if owns_terminal {
+            jobs::give_terminal(job.pgid.as_raw());
+        }
+        if resume {
+            job.resume(self.job_control);
+        }
+
+        job.wait();
+        if owns_terminal {
+            jobs::give_terminal(getpgrp().as_raw());
+        }
+
+        if job.is_stopped() {
+            let id = self.jobs.insert(job);
+            if let Some(job) = self.jobs.get(id) {
+                eprintln!("\n{}", self.jobs.format(job));
+            }
+            return 128 + Signal::SIGTSTP as i32;
+        }
+
+        job.status()
+    }
+
+    /// Continues a stopped job, either waiting for it in the foreground
+    /// (`fg`) or leaving it running in the background (`bg`).
+    pub fn resume_job(&mut self, id: usize, foreground: bool) -> i32 {
+        if foreground {
+            let Some(job) = self.jobs.remove(id) else {
+                return 1;
+            };
+            println!("{}", job.command);
+            return self.wait_foreground(job, true);
+        }
+
+        let job_control = self.job_control;
+        let Some(job) = self.jobs.get_mut(id) else {
+            return 1;
+        };
+        job.resume(job_control);
+        println!("[{id}] {} &", job.command);
+        self.jobs.touch(id);
+
+        0
+    }
+
+    /// Starts one stage of a pipeline. External programs are spawned
+    /// directly, builtins, functions and compound commands run in a forked
+    /// copy of the shell so that they can sit anywhere in the pipeline.
+    fn spawn_stage(
+        &mut self,
+        stage: Stage<'_>,
+        stdin: Option<PipeReader>,
+        stdout: Option<PipeWriter>,
+        group: Option<ProcessGroup>,
+    ) -> Result<Pid, CommandError> {
+        let cmd = match stage {
+            Stage::Cmd(cmd) => cmd,
+            Stage::Compound(command) => {
+                return self
+                    .fork_subshell(group, stdin, stdout, |shell| shell.run_command(&command))
+            }
+        };
+        if !self.is_builtin(&cmd) {
+            let child = internals::spawn(
+                cmd,
+                &self.vars,
+                stdin.map_or(Stdio::inherit(), Stdio::from),
+                stdout.map_or(Stdio::inherit(), Stdio::from),
+                group,
+            )?;
+            return Ok(Pid::from_raw(child.id() as i32));
+        }
+
+        self.fork_subshell(group, stdin, stdout, |shell| shell.run_cmd(cmd))
+    }
+
+    /// Runs `f` in a forked copy of the shell and returns the child's pid.
+    fn fork_subshell(
+        &mut self,
+        group: Option<ProcessGroup>,
+        stdin: Option<PipeReader>,
+        stdout: Option<PipeWriter>,
+        f: impl FnOnce(&mut Self) -> i32,
+    ) -> Result<Pid, CommandError> {
+        io::stdout().flush().map_err(CommandError::IOError)?;
+        match unsafe { fork() } {
+            Ok(ForkResult::Child) => {
+                if let Some(group) = group {
+                    jobs::join_group(group);
+                }
+                for sig in [
+                    Signal::SIGPIPE,
+                    Signal::SIGINT,
+                    Signal::SIGQUIT,
+                    Signal::SIGTSTP,
+                    Signal::SIGTTIN,
+                    Signal::SIGTTOU,
+                ] {
+                    let _ = unsafe { signal::signal(sig, SigHandler::SigDfl) };
+                }
+                if let Some(reader) = stdin {
+                    let _ = dup2(reader.as_raw_fd(), 0);
+                }
+                if let Some(writer) = stdout {
+                    let _ = dup2(writer.as_raw_fd(), 1);
+                }
+
+                self.job_control = false;
+                self.jobs = JobTable::new();
+                let status = f(self);
+                let _ = io::stdout().flush();
+                unsafe { libc::_exit(status) }
+            }
+            Ok(ForkResult::Parent { child }) => Ok(child),
+            Err(e) => Err(CommandError::IOError(e.into())),
+        }
+    }
+
+    fn run_cmd(&mut self, cmd: Cmd) -> i32 {
+        if let Some(body) = self.functions.get(&cmd.name).cloned() {
+            return self.call_function(&body, cmd);
+        }
+
+        match self.internals.get(&cmd.name).copied() {
+            Some(func) => match internals::redirect(&cmd.redirects) {
+                // Keep the guard alive so builtin errors follow `2>` too.
+                Ok(_guard) => exit_code(func(self, cmd)),
+                Err(e) => exit_code(Err(e)),
+            },
+            // `NAME=value` on its own sets shell variables.
+            None if cmd.name.is_empty() => {
+                exit_code(internals::redirect(&cmd.redirects).and_then(|_guard| {
+                    for (name, value) in cmd.variables_overrides {
+                        self.vars.set(&name, value)?;
+                    }
+                    Ok(internals::exit_status(self.last_substitution.unwrap_or(0)))
+                }))
+            }
+            None => exit_code(internals::run(cmd, &self.vars)),
+        }
+    }
+
+    /// Runs a command in the shell itself, without a pipeline around it.
+    fn run_command(&mut self, command: &Command) -> i32 {
+        match command {
+            Command::Simple(cmd) => match cmd.expand(self) {
+                Ok(cmd) => self.run_cmd(cmd),
+                Err(e) => exit_code(Err(e)),
+            },
+            Command::Compound(compound, redirects) => self.run_compound(compound, redirects),
+            Command::Function(name, body) => {
+                self.functions.insert(name.clone(), Rc::clone(body));
+                0
+            }
+        }
+    }
+
+    /// Runs a function with the arguments of `cmd` as positional parameters
+    /// and its assignments as exported local variables.
+    fn call_function(&mut self, body: &Command, cmd: Cmd) -> i32 {
+        let _guard = match internals::redirect(&cmd.redirects) {
+            Ok(guard) => guard,
+            Err(e) => return exit_code(Err(e)),
+        };
+        self.vars.push_scope();
+        let positional = std::mem::replace(&mut self.positional, cmd.args);
+        // Loops around the call cannot be left from inside the function.
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        self.function_depth += 1;
+
+        let assigned = cmd
+            .variables_overrides
+            .into_iter()
+            .try_for_each(|(name, value)| {
+                self.vars.make_local(&name)?;
+                self.vars.set(&name, value)?;
+                self.vars.set_exported(&name, true);
+                Ok(())
+            });
+        let status = match assigned {
+            Ok(()) => self.run_command(body),
+            Err(e) => exit_code(Err(e)),
+        };
+        if self.jump == Some(Jump::Return) {
+            self.jump = None;
+        }
+
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        self.positional = positional;
+        self.vars.pop_scope();
+
+        status
+    }
+
+    fn run_compound(&mut self, compound: &Compound, redirects: &[Redirect]) -> i32 {
+        let redirects = match redirects
+            .iter()
+            .map(|redirect| redirect.expand(self, &HashMap::new()))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(redirects) => redirects,
+            Err(e) => return exit_code(Err(e)),
+        };
+
+        match internals::redirect(&redirects) {
+            Ok(_guard) => match self.run_compound_body(compound) {
+                Ok(status) => status,
+                Err(e) => exit_code(Err(e)),
+            },
+            Err(e) => exit_code(Err(e)),
+        }
+    }
+
+    fn run_compound_body(&mut self, compound: &Compound) -> Result<i32, CommandError> {
+        match compound {
+            Compound::Group(list) => Ok(self.execute(list)),
+            // Like C, a non-zero value is true.
+            Compound::Arith(expr, Col(col)) => {
+                let value = expand_arithmetic(self, expr).map_err(|e| e.offset_column(*col))?;
+                Ok(i32::from(value == 0))
+            }
+            Compound::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    if self.execute(condition) == 0 {
+                        return Ok(self.execute(body));
+                    }
+                }
+                Ok(otherwise.as_ref().map_or(0, |body| self.execute(body)))
+            }
+            Compound::While {
+                until,
+                condition,
+                body,
+            } => self.run_loop(|shell| Ok((shell.execute(condition) == 0) != *until), body),
+            Compound::For { var, words, body } => {
+                let words = match words {
+                    Some(words) => expand_words(self, &HashMap::new(), words)?,
+                    None => self.positional.clone(),
+                };
+                let mut words = words.into_iter();
+                self.run_loop(
+                    |shell| match words.next() {
+                        Some(word) => shell.vars.set(var, word).map(|_| true),
+                        None => Ok(false),
+                    },
+                    body,
+                )
+            }
+            Compound::ArithFor {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                let evaluate = |shell: &mut Self, expr: &str| match expr.trim() {
+                    "" => Ok(1),
+                    expr => expand_arithmetic(shell, expr),
+                };
+                evaluate(self, init)?;
+                let mut first = true;
+                self.run_loop(
+                    |shell| {
+                        if !std::mem::take(&mut first) {
+                            evaluate(shell, step)?;
+                        }
+                        Ok(evaluate(shell, condition)? != 0)
+                    },
+                    body,
+                )
+            }
+            Compound::Case { word, arms } => {
+                let word = expand_vars_into_arg(self, &HashMap::new(), word)?;
+                for (patterns, body) in arms {
+                    for pattern in patterns {
+                        if pattern_matches(&expand_case_pattern(self, pattern)?, &word) {
+                            return Ok(if body.is_empty() {
+                                0
+                            } else {
+                                self.execute(body)
+                            });
+                        }
+                    }
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    /// Runs `body` for as long as `next` says so and returns the status of
+    /// its last run, or 0 if it never ran.
+    fn run_loop(
+        &mut self,
+        mut next: impl FnMut(&mut Self) -> Result<bool, CommandError>,
+        body: &CmdList,
+    ) -> Result<i32, CommandError> {
+        self.loop_depth += 1;
+        let mut status = 0;
+        let result = loop {
+            let proceed = match next(self) {
+                Ok(proceed) => proceed,
+                Err(e) => break Err(e),
+            };
+            if self.end_iteration() || !proceed {
+                break Ok(status);
+            }
+            status = self.execute(body);
+            if self.end_iteration() {
+                break Ok(status);
+            }
+        };
+        self.loop_depth -= 1;
+
+        result
+    }
+
+    /// Consumes a pending `break` or `continue` aimed at the innermost loop
+    /// and tells whether that loop has to stop.
+    fn end_iteration(&mut self) -> bool {
+        match self.jump.take() {
+            None | Some(Jump::Continue(1)) => false,
+            Some(Jump::Break(1)) => true,
+            Some(Jump::Break(n)) => {
+                self.jump = Some(Jump::Break(n - 1));
+                true
+            }
+            Some(Jump::Continue(n)) => {
+                self.jump = Some(Jump::Continue(n - 1));
+                true
+            }
+            Some(jump @ (Jump::Return | Jump::Exit)) => {
+                self.jump = Some(jump);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn run_line(executor: &mut Executor, line: &str) -> i32 {
+    executor.execute(&CmdParser::parse(line).unwrap())
+}
+
+#[test]
+fn test_execute_status() {
+    let mut executor = Executor::new();
+
+    assert_eq!(run_line(&mut executor, "true"), 0);
+    assert_eq!(run_line(&mut executor, "false"), 1);
+    assert_eq!(executor.last_status, 1);
+    assert_eq!(run_line(&mut executor, "dsh-no-such-program"), 127);
+}
+
+#[test]
+fn test_execute_lists() {
+    let mut executor = Executor::new();
+
+    assert_eq!(run_line(&mut executor, "true && false"), 1);
+    assert_eq!(run_line(&mut executor, "false && dsh-no-such-program"), 1);
+    assert_eq!(run_line(&mut executor, "true || dsh-no-such-program"), 0);
+    assert_eq!(run_line(&mut executor, "false || true"), 0);
+    assert_eq!(run_line(&mut executor, "false && true || true"), 0);
+    assert_eq!(run_line(&mut executor, "true || false && false"), 1);
+    assert_eq!(run_line(&mut executor, "false; true"), 0);
+    assert_eq!(run_line(&mut executor, "true; false"), 1);
+}
+
+#[test]
+fn test_execute_pipeline() {
+    let mut executor = Executor::new();
+
+    assert_eq!(
+        run_line(&mut executor, "printf 'a\nb\n' | grep b | grep -q b"),
+        0
+    );
+    assert_eq!(run_line(&mut executor, "printf 'a\n' | grep -q b"), 1);
+    assert_eq!(run_line(&mut executor, "false | true"), 0);
+    assert_eq!(run_line(&mut executor, "true | false"), 1);
+    assert_eq!(run_line(&mut executor, "cd / | dsh-no-such-program"), 127);
+    assert_eq!(run_line(&mut executor, "dsh-no-such-program | cd /"), 0);
+}
+
+#[test]
+fn test_execute_redirects() {
+    let dir = std::env::temp_dir().join(format!("dsh-redirects-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.display();
+    let mut executor = Executor::new();
+
+    run_line(
+        &mut executor,
+        &format!("echo one > {dir}/out; echo two >> {dir}/out"),
+    );
+    assert_eq!(
+        std::fs::read_to_string(format!("{dir}/out")).unwrap(),
+        "one\ntwo\n"
+    );
+    assert_eq!(
+        run_line(&mut executor, &format!("grep -q two < {dir}/out")),
+        0
+    );
+
+    run_line(&mut executor, &format!("ls {dir}/missing > {dir}/all 2>&1"));
+    assert!(!std::fs::read_to_string(format!("{dir}/all"))
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(run_line(&mut executor, &format!("> {dir}/empty")), 0);
+    assert!(std::fs::read_to_string(format!("{dir}/empty"))
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(run_line(&mut executor, &format!("cat < {dir}/missing")), 1);
+
+    // The file must not be left on fd 3 itself, either for a program or for
+    // the shell running a compound command, here in a subshell.
+    let line = format!("sh -c 'echo hi >&3' 3> {dir}/fd3");
+    assert_eq!(run_line(&mut executor, &line), 0);
+    assert_eq!(
+        std::fs::read_to_string(format!("{dir}/fd3")).unwrap(),
+        "hi\n"
+    );
+    let line = format!("{{ echo there >&3; }} 3> {dir}/fd3");
+    let list = CmdParser::parse(&line).unwrap();
+    assert_eq!(executor.capture(&list).unwrap(), ("".into(), 0));
+    assert_eq!(
+        std::fs::read_to_string(format!("{dir}/fd3")).unwrap(),
+        "there\n"
+    );
+    std::fs::remove_dir_all(format!("{dir}")).unwrap();
+}
+
+#[test]
+fn test_execute_background() {
+    let mut executor = Executor::new();
+
+    assert_eq!(run_line(&mut executor, "sleep 0.1 && false &"), 0);
+    assert_eq!(run_line(&mut executor, "true | sleep 0.1 &"), 0);
+    assert_eq!(executor.jobs.ids(), vec![1, 2]);
+
+    for id in executor.jobs.ids() {
+        executor.jobs.get_mut(id).unwrap().wait();
+    }
+    let done = executor.jobs.reap();
+    assert_eq!(done.len(), 2);
+    assert!(done[0].starts_with("[1]-  Exit 1"));
+    assert!(done[1].starts_with("[2]+  Done"));
+    assert!(executor.jobs.is_empty());
+}
+
+#[test]
+fn test_execute_env_overrides() {
+    let mut executor = Executor::new();
+    executor
+        .vars
+        .set("DSH_TEST_SHELL_ONLY", "1".into())
+        .unwrap();
+
+    assert_eq!(
+        run_line(
+            &mut executor,
+            "DSH_TEST_OVERRIDE=a sh -c 'test \"$DSH_TEST_OVERRIDE\" = a'"
+        ),
+        0
+    );
+    assert_eq!(
+        run_line(
+            &mut executor,
+            "A=1 DSH_TEST_OVERRIDE=$A sh -c 'test \"$DSH_TEST_OVERRIDE\" = 1'"
+        ),
+        0
+    );
+    assert_eq!(
+        run_line(&mut executor, "sh -c 'test -z \"$DSH_TEST_SHELL_ONLY\"'"),
+        0
+    );
+
+    assert!(std::env::var("DSH_TEST_OVERRIDE").is_err());
+    assert!(std::env::var("A").is_err());
+    assert_eq!(executor.vars.get("DSH_TEST_OVERRIDE"), None);
+}
+
+#[test]
+fn test_execute_variables() {
+    let mut executor = Executor::new();
+
+    assert_eq!(run_line(&mut executor, "DSH_TEST_VAR=one"), 0);
+    assert_eq!(executor.vars.get("DSH_TEST_VAR"), Some("one"));
+    assert!(std::env::var("DSH_TEST_VAR").is_err());
+    assert_eq!(
+        run_line(&mut executor, "sh -c 'test -z \"$DSH_TEST_VAR\"'"),
+        0
+    );
+
+    run_line(&mut executor, "export DSH_TEST_VAR");
+    assert_eq!(
+        run_line(&mut executor, "sh -c 'test \"$DSH_TEST_VAR\" = one'"),
+        0
+    );
+    run_line(&mut executor, "export -n DSH_TEST_VAR");
+    assert_eq!(
+        run_line(&mut executor, "sh -c 'test -z \"$DSH_TEST_VAR\"'"),
+        0
+    );
+
+    assert_eq!(run_line(&mut executor, "readonly DSH_TEST_VAR=two"), 0);
+    assert_eq!(run_line(&mut executor, "DSH_TEST_VAR=three"), 1);
+    assert_eq!(run_line(&mut executor, "unset DSH_TEST_VAR"), 1);
+    assert_eq!(executor.vars.get("DSH_TEST_VAR"), Some("two"));
+
+    run_line(&mut executor, "DSH_TEST_OTHER=x; unset DSH_TEST_OTHER");
+    assert_eq!(executor.vars.get("DSH_TEST_OTHER"), None);
+    assert_eq!(run_line(&mut executor, "export 1abc=x"), 1);
+}
+
+#[test]
+fn test_execute_substitution_status() {
+    let mut executor = Executor::new();
+
+    assert_eq!(run_line(&mut executor, "DSH_TEST_OUT=$(false)"), 1);
+    assert_eq!(run_line(&mut executor, "DSH_TEST_OUT=$(echo ok)"), 0);
+    assert_eq!(executor.vars.get("DSH_TEST_OUT"), Some("ok"));
+    assert_eq!(run_line(&mut executor, "test \"$(echo a b)\" = 'a b'"), 0);
+}
+
+#[test]
+fn test_execute_arithmetic() {
+    let mut executor = Executor::new();
+
+    assert_eq!(run_line(&mut executor, "(( DSH_TEST_N = 2 + 3 ))"), 0);
+    assert_eq!(executor.vars.get("DSH_TEST_N"), Some("5"));
+    assert_eq!(run_line(&mut executor, "((DSH_TEST_N++)) && ((0))"), 1);
+    assert_eq!(executor.vars.get("DSH_TEST_N"), Some("6"));
+    assert_eq!(run_line(&mut executor, "(( 1 / 0 ))"), 1);
+    assert_eq!(run_line(&mut executor, "(( $(echo 2) * 3 == 6 ))"), 0);
+    assert_eq!(run_line(&mut executor, "((1)) | grep -q x"), 1);
+    assert_eq!(run_line(&mut executor, "true | ((DSH_TEST_N - 6))"), 1);
+
+    // Errors point at the operator on the line, not in the expression.
+    let list = CmdParser::parse("echo x $((1/0))").unwrap();
+    let Command::Simple(cmd) = &list[0].first.cmds[0] else {
+        unreachable!()
+    };
+    assert!(matches!(
+        cmd.expand(&mut executor),
+        Err(CommandError::Arithmetic(_, Col(11)))
+    ));
+    let list = CmdParser::parse("((  2 % 0 ))").unwrap();
+    let Command::Compound(compound, _) = &list[0].first.cmds[0] else {
+        unreachable!()
+    };
+    assert!(matches!(
+        executor.run_compound_body(compound),
+        Err(CommandError::Arithmetic(_, Col(6)))
+    ));
+}
+
+#[test]
+fn test_execute_control_flow() {
+    let mut executor = Executor::new();
+    let mut output = |line: &str| {
+        let list = CmdParser::parse(line).unwrap();
+        executor.capture(&list).unwrap()
+    };
+
+    assert_eq!(
+        output("if false; then echo a; elif true; then echo b; else echo c; fi"),
+        ("b".into(), 0)
+    );
+    assert_eq!(output("if false; then echo a; fi"), ("".into(), 0));
+    assert_eq!(
+        output("for x in a{1,2} 'b c'; do echo \"$x\"; done"),
+        ("a1\na2\nb c".into(), 0)
+    );
+    assert_eq!(
+        output("i=0; while ((i < 5)); do ((i++ == 3)) && break; echo $i; done"),
+        ("1\n2\n3".into(), 0)
+    );
+    assert_eq!(
+        output("i=0; until ((i >= 3)); do ((i++)); echo $i; done | tail -1"),
+        ("3".into(), 0)
+    );
+    assert_eq!(
+        output("for ((i = 0; i < 3; i++)); do for j in a b; do [ $j = b ] && continue 2; echo $i$j; done; done"),
+        ("0a\n1a\n2a".into(), 0)
+    );
+    assert_eq!(
+        output("for x in 1 2; do for y in 1 2; do break 2; done; echo no; done; echo $x$y"),
+        ("11".into(), 0)
+    );
+    assert_eq!(
+        output("case foo.rs in *.txt) echo txt;; '*.rs'|f*.rs) echo rs;; esac"),
+        ("rs".into(), 0)
+    );
+    assert_eq!(output("case x in y) false;; esac"), ("".into(), 0));
+    assert_eq!(output("break"), ("".into(), 1));
+    assert_eq!(
+        output("while true; do break; done; echo $?"),
+        ("0".into(), 0)
+    );
+}
+
+#[test]
+fn test_execute_functions() {
+    let mut executor = Executor::new();
+    run_line(
+        &mut executor,
+        "greet() { echo \"hi $1 ($#)\"; }
+         function shout { echo \"$@\" | tr a-z A-Z; }
+         f() { local x=local; g; return 3; echo no; }; g() { echo $x; }
+         first() { for i in 1 2 3; do [ $i = 2 ] && return; done; }
+         env() { sh -c 'echo $V'; }",
+    );
+    let mut output = |line: &str| {
+        let list = CmdParser::parse(line).unwrap();
+        executor.capture(&list).unwrap()
+    };
+
+    assert_eq!(output("greet you there"), ("hi you (2)".into(), 0));
+    assert_eq!(output("shout a b | cat"), ("A B".into(), 0));
+    assert_eq!(
+        output("x=global; f; echo $? $x"),
+        ("local\n3 global".into(), 0)
+    );
+    assert_eq!(output("first; echo $i"), ("2".into(), 0));
+    assert_eq!(output("V=2 env; echo \"[$V]\""), ("2\n[]".into(), 0));
+    assert_eq!(output("return"), ("".into(), 1));
+
+    assert_eq!(run_line(&mut executor, "declare -f greet"), 0);
+    assert_eq!(run_line(&mut executor, "unset -f greet; greet"), 127);
+    assert_eq!(run_line(&mut executor, "declare -f greet"), 1);
+}
+
+#[test]
+fn test_execute_aliases() {
+    let mut executor = Executor::new();
+    run_line(
+        &mut executor,
+        "alias say='echo said' quiet='grep -q' both='say one; say two' it='it '",
+    );
+    let mut output = |line: &str| {
+        let list = CmdParser::parse(line).unwrap();
+        executor.capture(&list).unwrap()
+    };
+
+    assert_eq!(output("say \"it's\""), ("said it's".into(), 0));
+    assert_eq!(
+        output("both | tr a-z A-Z"),
+        ("SAID ONE\nSAID TWO".into(), 0)
+    );
+    assert_eq!(
+        output("say x | quiet x && say found"),
+        ("said found".into(), 0)
+    );
+    assert_eq!(output("it it say"), ("".into(), 127));
+
+    assert_eq!(run_line(&mut executor, "unalias say; alias say"), 1);
+    assert_eq!(run_line(&mut executor, "unalias -a"), 0);
+    assert!(executor.aliases.is_empty());
+}
+
+#[test]
+fn test_execute_history() {
+    let mut executor = Executor::new();
+    for line in ["ls", "echo one", "echo two", "make"] {
+        executor.history.add(line).unwrap();
+    }
+    let lines = |executor: &Executor| -> Vec<String> {
+        let entries = executor.history.entries();
+        entries.iter().map(|entry| entry.line.clone()).collect()
+    };
+
+    assert_eq!(run_line(&mut executor, "history echo; history 2"), 0);
+    assert_eq!(run_line(&mut executor, "history -d 2"), 0);
+    assert_eq!(lines(&executor), ["ls", "echo two", "make"]);
+    assert_eq!(run_line(&mut executor, "history -d 4"), 1);
+    assert_eq!(run_line(&mut executor, "history -x"), 2);
+
+    let path = std::env::temp_dir().join(format!("dsh-history-builtin-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    assert_eq!(run_line(&mut executor, &format!("history -w {path}")), 0);
+    assert_eq!(run_line(&mut executor, "history -c"), 0);
+    assert!(executor.history.is_empty());
+    assert_eq!(run_line(&mut executor, &format!("history -r {path}")), 0);
+    assert_eq!(lines(&executor), ["ls", "echo two", "make"]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_run_script() {
+    let mut executor = Executor::new();
+
+    assert_eq!(
+        executor.run_script("#!/usr/bin/dsh\nfor x in 1 2; do\n  false\ndone\n"),
+        1
+    );
+    assert_eq!(executor.run_script("fi\ntrue\n"), 2);
+    assert_eq!(executor.run_script("true\nfi\nDSH_TEST_AFTER=1\ntrue\n"), 2);
+    assert_eq!(executor.vars.get("DSH_TEST_AFTER"), None);
+    assert_eq!(executor.run_script("if true; then\n"), 2);
+    assert_eq!(
+        executor.run_script("f() { exit 3; }\nwhile true; do f; done\ntrue\n"),
+        3
+    );
+
+    // Each command runs before the next line is read.
+    let mut executor = Executor::new();
+    let path = std::env::temp_dir().join(format!("dsh-run-lines-{}", std::process::id()));
+    let mut lines = vec![format!("echo one > {}\n", path.display())].into_iter();
+    let next_line = || {
+        lines
+            .next()
+            .or_else(|| Some(format!("DSH_TEST_READ={}", fs::read_to_string(&path).ok()?)))
+    };
+    assert_eq!(executor.run_lines(std::iter::from_fn(next_line).take(2)), 0);
+    assert_eq!(executor.vars.get("DSH_TEST_READ"), Some("one"));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_execute_source() {
+    let dir = std::env::temp_dir().join(format!("dsh-source-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("lib.dsh"),
+        "DSH_TEST_ARGS=\"$*\"\nalias dsh_ll='ls -l'\n(( $# )) && return 7\nDSH_TEST_END=1\n",
+    )
+    .unwrap();
+    let mut executor = Executor::new();
+    executor.positional = vec!["outer".into()];
+    executor
+        .vars
+        .set("PATH", dir.display().to_string())
+        .unwrap();
+
+    assert_eq!(run_line(&mut executor, "source lib.dsh a b"), 7);
+    assert_eq!(executor.vars.get("DSH_TEST_ARGS"), Some("a b"));
+    assert_eq!(executor.vars.get("DSH_TEST_END"), None);
+    assert_eq!(executor.positional, ["outer"]);
+    assert!(executor.aliases.contains_key("dsh_ll"));
+
+    executor.positional.clear();
+    assert_eq!(run_line(&mut executor, ". lib.dsh"), 0);
+    assert_eq!(executor.vars.get("DSH_TEST_END"), Some("1"));
+    assert_eq!(run_line(&mut executor, "source dsh-no-such-file"), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_execute_glob_no_match() {
+    let mut executor = Executor::new();
+    let pattern = "/dsh-no-such-dir/*";
+
+    assert_eq!(
+        run_line(&mut executor, &format!("test {pattern} = '{pattern}'")),
+        0
+    );
+    run_line(&mut executor, "shopt -s nullglob");
+    assert_eq!(run_line(&mut executor, &format!("test -z {pattern}")), 0);
+    run_line(&mut executor, "shopt -s failglob");
+    assert_eq!(run_line(&mut executor, &format!("true {pattern}")), 1);
+    run_line(&mut executor, "shopt -u failglob");
+    assert_eq!(
+        run_line(&mut executor, &format!("test {pattern} = '{pattern}'")),
+        0
+    );
+}
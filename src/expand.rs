@@ -0,0 +1,1078 @@
+use std::collections::HashMap;
+
+use nix::unistd::{getuid, User};
+
+use crate::{
+    arith,
+    cmd::{CmdParser, Col},
+    error::{CmdParsingError, CommandError},
+    exec::Executor,
+    glob::{glob, NoMatch},
+    vars::is_valid_name,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    /// Bare text of the word itself.
+    Literal,
+    /// Quoted or escaped text: never split, never a pattern.
+    Quoted,
+    /// Unquoted result of an expansion, subject to field splitting.
+    Expanded,
+}
+
+type Field = Vec<(char, Origin)>;
+
+/// Fields produced while expanding a single word.
+#[derive(Default)]
+struct Fields {
+    done: Vec<(Field, bool)>,
+    current: Field,
+    /// Whether `current` is kept even when empty, as it is for `""`.
+    keep: bool,
+    /// Set by a `"$@"` without positional parameters, which expands to no
+    /// field at all despite its quotes.
+    empty_at: bool,
+}
+
+impl Fields {
+    fn push(&mut self, c: char, origin: Origin) {
+        self.current.push((c, origin));
+    }
+
+    fn push_str(&mut self, s: &str, origin: Origin) {
+        self.current.extend(s.chars().map(|c| (c, origin)));
+    }
+
+    /// Starts a new field, as between the parameters of `"$@"`.
+    fn split(&mut self) {
+        let current = std::mem::take(&mut self.current);
+        self.done.push((current, true));
+        self.keep = true;
+    }
+
+    fn finish(mut self) -> Vec<(Field, bool)> {
+        let keep = self.keep && !(self.empty_at && self.current.is_empty());
+        self.done.push((self.current, keep));
+        self.done
+    }
+}
+
+fn field_text(field: &Field) -> String {
+    field.iter().map(|(c, _)| c).collect()
+}
+
+/// Turns `field` into a pattern where quoted characters match literally.
+fn field_pattern(field: &Field) -> String {
+    let mut pattern = String::new();
+    for &(c, origin) in field {
+        if origin == Origin::Quoted && matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+/// Characters of `fields` with quoting removed, fields joined by spaces.
+fn to_string(fields: Vec<(Field, bool)>) -> String {
+    let fields: Vec<String> = fields.iter().map(|(field, _)| field_text(field)).collect();
+    fields.join(" ")
+}
+
+fn to_pattern(fields: Vec<(Field, bool)>) -> String {
+    fields
+        .iter()
+        .map(|(field, _)| field_pattern(field))
+        .collect()
+}
+
+/// Splits every field on the `ifs` characters that came out of unquoted
+/// expansions.
+fn split_fields(fields: Vec<(Field, bool)>, ifs: &str) -> Vec<Field> {
+    let mut words = vec![];
+    for (field, keep) in fields {
+        let mut word = vec![];
+        let mut pending = keep;
+        let mut after_delimiter = true;
+        for (c, origin) in field {
+            if origin != Origin::Expanded || !ifs.contains(c) {
+                word.push((c, origin));
+                pending = true;
+                after_delimiter = false;
+            } else if c.is_whitespace() {
+                if pending {
+                    words.push(std::mem::take(&mut word));
+                    pending = false;
+                }
+            } else {
+                if pending || after_delimiter {
+                    words.push(std::mem::take(&mut word));
+                }
+                pending = false;
+                after_delimiter = true;
+            }
+        }
+        if pending {
+            words.push(word);
+        }
+    }
+    words
+}
+
+/// Index of the `close` bracket matching an `open` one right before
+/// `start`, skipping quoted text and backquotes.
+fn closing(chars: &[char], start: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 1;
+    let mut quote = None;
+    let mut i = start;
+    while i < chars.len() {
+        match (chars[i], quote) {
+            ('\\', q) if q != Some('\'') => i += 1,
+            (c @ ('\'' | '"' | '`'), None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c == open => depth += 1,
+            (c, None) if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Removes the backslashes that only protect `$`, `` ` `` and `\` (and `"`
+/// within double quotes) from the text of a `` `...` `` substitution.
+fn unescape_backquoted(text: &str, quoted: bool) -> String {
+    let mut command = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(&next) = chars.peek() {
+                if matches!(next, '$' | '`' | '\\') || (quoted && next == '"') {
+                    command.push(next);
+                    chars.next();
+                    continue;
+                }
+            }
+        }
+        command.push(c);
+    }
+    command
+}
+
+/// Splits the word of `${NAME/pattern/replacement}` on its first unquoted
+/// `/`.
+fn split_replacement(word: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in word.char_indices() {
+        match (c, quote) {
+            _ if escaped => escaped = false,
+            ('\\', _) => escaped = true,
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('/', None) => return (&word[..i], &word[i + 1..]),
+            _ => {}
+        }
+    }
+    (word, "")
+}
+
+fn bad_substitution(inner: &str) -> CommandError {
+    CommandError::Custom {
+        prog_name: "dsh".into(),
+        message: format!("${{{inner}}}: bad substitution"),
+        status: 1,
+    }
+}
+
+/// Byte offsets of every char boundary of `s`, `s.len()` included.
+fn boundaries(s: &str) -> Vec<usize> {
+    s.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(s.len()))
+        .collect()
+}
+
+fn remove_prefix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut ends = boundaries(value);
+    if longest {
+        ends.reverse();
+    }
+    match ends
+        .into_iter()
+        .find(|i| pattern_matches(pattern, &value[..*i]))
+    {
+        Some(i) => value[i..].to_string(),
+        None => value.to_string(),
+    }
+}
+
+fn remove_suffix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut starts = boundaries(value);
+    if !longest {
+        starts.reverse();
+    }
+    match starts
+        .into_iter()
+        .find(|i| pattern_matches(pattern, &value[*i..]))
+    {
+        Some(i) => value[..i].to_string(),
+        None => value.to_string(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Replace {
+    First,
+    All,
+    Prefix,
+    Suffix,
+}
+
+fn replace(value: &str, pattern: &str, replacement: &str, mode: Replace) -> String {
+    let bounds = boundaries(value);
+    // End of the longest match starting at `start`.
+    let longest_at = |start: usize| {
+        bounds
+            .iter()
+            .rev()
+            .copied()
+            .filter(|end| *end >= start)
+            .find(|end| pattern_matches(pattern, &value[start..*end]))
+    };
+
+    match mode {
+        Replace::Prefix => match longest_at(0) {
+            Some(end) => format!("{replacement}{}", &value[end..]),
+            None => value.to_string(),
+        },
+        Replace::Suffix => match bounds
+            .iter()
+            .find(|start| pattern_matches(pattern, &value[**start..]))
+        {
+            Some(start) => format!("{}{replacement}", &value[..*start]),
+            None => value.to_string(),
+        },
+        Replace::First | Replace::All => {
+            let mut result = String::new();
+            let mut start = 0;
+            while let Some(c) = value[start..].chars().next() {
+                match longest_at(start) {
+                    Some(end) if end > start => {
+                        result.push_str(replacement);
+                        start = end;
+                        if mode == Replace::First {
+                            break;
+                        }
+                    }
+                    _ => {
+                        result.push(c);
+                        start += c.len_utf8();
+                    }
+                }
+            }
+            result + &value[start..]
+        }
+    }
+}
+
+/// Length of a `[...]` class starting at `p[i]`, and whether it matches `c`.
+/// `None` when the bracket is not closed and so stands for itself.
+fn match_class(p: &[char], i: usize, c: char) -> Option<(usize, bool)> {
+    let mut j = i + 1;
+    let negated = matches!(p.get(j), Some('!' | '^'));
+    if negated {
+        j += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &start = p.get(j)?;
+        if start == ']' && !first {
+            return Some((j + 1, matched != negated));
+        }
+        first = false;
+
+        if start == '[' && p.get(j + 1) == Some(&':') {
+            let rest: String = p[j + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                let class = &rest[..end];
+                matched |= match class {
+                    "alpha" => c.is_alphabetic(),
+                    "digit" => c.is_ascii_digit(),
+                    "alnum" => c.is_alphanumeric(),
+                    "upper" => c.is_uppercase(),
+                    "lower" => c.is_lowercase(),
+                    "space" => c.is_whitespace(),
+                    "punct" => c.is_ascii_punctuation(),
+                    "xdigit" => c.is_ascii_hexdigit(),
+                    _ => false,
+                };
+                j += 2 + class.chars().count() + 2;
+                continue;
+            }
+        }
+
+        let (low, next) = match start {
+            '\\' => (*p.get(j + 1)?, j + 2),
+            _ => (start, j + 1),
+        };
+        if p.get(next) == Some(&'-') && p.get(next + 1).is_some_and(|c| *c != ']') {
+            let (high, after) = match p[next + 1] {
+                '\\' => (*p.get(next + 2)?, next + 3),
+                high => (high, next + 2),
+            };
+            matched |= low <= c && c <= high;
+            j = after;
+        } else {
+            matched |= low == c;
+            j = next;
+        }
+    }
+}
+
+/// Whether `pattern` has any unescaped `*`, `?` or `[...]`.
+pub fn is_pattern(pattern: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < p.len() {
+        match p[i] {
+            '\\' => i += 1,
+            '*' | '?' => return true,
+            '[' if match_class(&p, i, '\0').is_some() => return true,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Whether `text` matches the shell `pattern` as a whole: `*`, `?`, `[...]`
+/// and `\` escapes.
+pub fn pattern_matches(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // Position of the last `*` and of the text it was tried against.
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        let next = match p.get(pi) {
+            Some('*') => {
+                star = Some((pi, ti));
+                pi += 1;
+                continue;
+            }
+            Some('?') => Some(pi + 1),
+            Some('[') => match match_class(&p, pi, t[ti]) {
+                Some((next, true)) => Some(next),
+                Some((_, false)) => None,
+                None => (t[ti] == '[').then_some(pi + 1),
+            },
+            Some('\\') if pi + 1 < p.len() => (p[pi + 1] == t[ti]).then_some(pi + 2),
+            Some(&c) => (c == t[ti]).then_some(pi + 1),
+            None => None,
+        };
+
+        match (next, star) {
+            (Some(next), _) => {
+                pi = next;
+                ti += 1;
+            }
+            (None, Some((star_pi, star_ti))) => {
+                pi = star_pi + 1;
+                ti = star_ti + 1;
+                star = Some((star_pi, ti));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Expands words of one command against the shell state. `overrides` are the
+/// command's own assignments, seen before shell variables.
+struct Expander<'a> {
+    executor: &'a mut Executor,
+    overrides: &'a HashMap<String, String>,
+    /// Here-document bodies keep their double quotes.
+    heredoc: bool,
+    /// Assignment values also get tilde expansion after each `:`.
+    assignment: bool,
+}
+
+impl Expander<'_> {
+    fn lookup(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.overrides.get(name) {
+            return Some(value.clone());
+        }
+
+        let executor = &self.executor;
+        match name {
+            "?" => Some(executor.last_status.to_string()),
+            "$" => Some(executor.pid.to_string()),
+            "!" => executor.last_background.map(|pid| pid.to_string()),
+            "#" => Some(executor.positional.len().to_string()),
+            "0" => Some(executor.arg0.clone()),
+            "@" | "*" if executor.positional.is_empty() => None,
+            "@" | "*" => Some(executor.positional.join(" ")),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                // `${00}` is `$0` too.
+                match name.parse::<usize>().ok()? {
+                    0 => Some(executor.arg0.clone()),
+                    index => executor.positional.get(index - 1).cloned(),
+                }
+            }
+            _ => executor.vars.get(name).map(String::from),
+        }
+    }
+
+    fn ifs(&self) -> String {
+        self.lookup("IFS").unwrap_or_else(|| " \t\n".into())
+    }
+
+    /// Appends the value of parameter `name` to `out`.
+    fn insert(&self, name: &str, value: Option<String>, quoted: bool, out: &mut Fields) {
+        let origin = if quoted {
+            Origin::Quoted
+        } else {
+            Origin::Expanded
+        };
+        match name {
+            "@" if quoted => {
+                if self.executor.positional.is_empty() {
+                    out.empty_at = true;
+                }
+                for (i, param) in self.executor.positional.iter().enumerate() {
+                    if i > 0 {
+                        out.split();
+                    }
+                    out.push_str(param, origin);
+                }
+            }
+            "*" if quoted => {
+                let separator: String = self.ifs().chars().take(1).collect();
+                out.push_str(&self.executor.positional.join(&separator), origin);
+            }
+            _ => out.push_str(&value.unwrap_or_default(), origin),
+        }
+    }
+
+    fn expand(&mut self, text: &str, quoted: bool, out: &mut Fields) -> Result<(), CommandError> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut in_double_quotes = quoted;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            i += 1;
+            match c {
+                '~' if !in_double_quotes
+                    && (i == 1 || (self.assignment && chars[i - 2] == ':')) =>
+                {
+                    match self.tilde(&chars[i..]) {
+                        Some((path, len)) => {
+                            out.push_str(&path, Origin::Quoted);
+                            i += len;
+                        }
+                        None => out.push(c, Origin::Literal),
+                    }
+                }
+                '\\' => {
+                    let Some(&next) = chars.get(i) else {
+                        continue;
+                    };
+                    i += 1;
+                    let escapable =
+                        matches!(next, '$' | '`' | '\\' | '\n') || (next == '"' && !self.heredoc);
+                    if in_double_quotes && !escapable {
+                        out.push('\\', Origin::Quoted);
+                    }
+                    if next != '\n' || !in_double_quotes {
+                        out.push(next, Origin::Quoted);
+                    }
+                }
+                '\'' if !in_double_quotes => {
+                    out.keep = true;
+                    while let Some(&c) = chars.get(i) {
+                        i += 1;
+                        if c == '\'' {
+                            break;
+                        }
+                        out.push(c, Origin::Quoted);
+                    }
+                }
+                '"' if !self.heredoc => {
+                    in_double_quotes = !in_double_quotes;
+                    out.keep = true;
+                }
+                '$' => i = self.dollar(&chars, i, in_double_quotes, out)?,
+                '`' => {
+                    let len = chars[i..]
+                        .iter()
+                        .scan(false, |escaped, c| {
+                            let end = *c == '`' && !*escaped;
+                            *escaped = *c == '\\' && !*escaped;
+                            Some(end)
+                        })
+                        .take_while(|end| !end)
+                        .count();
+                    let text: String = chars[i..i + len].iter().collect();
+                    let command = unescape_backquoted(&text, in_double_quotes);
+                    self.substitute(&command, in_double_quotes, out)?;
+                    i += len + 1;
+                }
+                _ if in_double_quotes => out.push(c, Origin::Quoted),
+                _ => out.push(c, Origin::Literal),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Expands the tilde-prefix that `rest` starts right after a `~`, returning
+    /// the path and the length of the prefix. Quoted prefixes are kept as is.
+    fn tilde(&self, rest: &[char]) -> Option<(String, usize)> {
+        let len = rest
+            .iter()
+            .take_while(|c| **c != '/' && !(self.assignment && **c == ':'))
+            .count();
+        let prefix: String = rest[..len].iter().collect();
+        if prefix.contains(['\'', '"', '\\', '$', '`']) {
+            return None;
+        }
+
+        let path = match prefix.as_str() {
+            "" => self.lookup("HOME").or_else(|| {
+                let user = User::from_uid(getuid()).ok()??;
+                Some(user.dir.display().to_string())
+            })?,
+            "+" => self.lookup("PWD")?,
+            "-" => self.lookup("OLDPWD")?,
+            name => {
+                let user = User::from_name(name).ok()??;
+                user.dir.display().to_string()
+            }
+        };
+        Some((path, len))
+    }
+
+    /// Expands the parameter following a `$` at `chars[i - 1]` and returns
+    /// the index right after it.
+    fn dollar(
+        &mut self,
+        chars: &[char],
+        i: usize,
+        quoted: bool,
+        out: &mut Fields,
+    ) -> Result<usize, CommandError> {
+        match chars.get(i) {
+            Some('{') => {
+                let Some(end) = closing(chars, i + 1, '{', '}') else {
+                    let inner: String = chars[i + 1..].iter().collect();
+                    return Err(bad_substitution(&inner));
+                };
+                let inner: String = chars[i + 1..end].iter().collect();
+                self.braced(&inner, quoted, out)?;
+                Ok(end + 1)
+            }
+            Some('(') => {
+                let Some(end) = closing(chars, i + 1, '(', ')') else {
+                    return Err(CommandError::Parse(CmdParsingError::UnterminatedExpansion(
+                        "$(",
+                        Col(i as u32 - 1),
+                    )));
+                };
+                let arithmetic = chars.get(i + 1) == Some(&'(')
+                    && chars[end - 1] == ')'
+                    && closing(chars, i + 2, '(', ')') == Some(end - 1);
+                if arithmetic {
+                    let expr: String = chars[i + 2..end - 1].iter().collect();
+                    // Errors point into the word rather than the expression.
+                    let value = self
+                        .expand_string(&expr, true)
+                        .and_then(|expr| arith::evaluate(&expr, &mut self.executor.vars))
+                        .map_err(|e| e.offset_column(i as u32 + 2))?;
+                    self.insert("", Some(value.to_string()), quoted, out);
+                } else {
+                    let command: String = chars[i + 1..end].iter().collect();
+                    self.substitute(&command, quoted, out)?;
+                }
+                Ok(end + 1)
+            }
+            Some(&c) if matches!(c, '?' | '$' | '!' | '#' | '@' | '*') || c.is_ascii_digit() => {
+                let name = c.to_string();
+                self.insert(&name, self.lookup(&name), quoted, out);
+                Ok(i + 1)
+            }
+            Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count();
+                let name: String = chars[i..i + len].iter().collect();
+                self.insert(&name, self.lookup(&name), quoted, out);
+                Ok(i + len)
+            }
+            _ => {
+                let origin = if quoted {
+                    Origin::Quoted
+                } else {
+                    Origin::Literal
+                };
+                out.push('$', origin);
+                Ok(i)
+            }
+        }
+    }
+
+    /// Runs `command` and appends its output, unquoted output being split
+    /// like any other expansion.
+    fn substitute(
+        &mut self,
+        command: &str,
+        quoted: bool,
+        out: &mut Fields,
+    ) -> Result<(), CommandError> {
+        let list = CmdParser::parse(command).map_err(CommandError::Parse)?;
+        let (output, status) = self.executor.capture(&list)?;
+        self.executor.last_status = status;
+        self.executor.last_substitution = Some(status);
+        self.insert("", Some(output), quoted, out);
+        Ok(())
+    }
+
+    /// Expands the word of `${NAME:-word}` or `${NAME:+word}` into `out`. Its
+    /// bare text is a result of the outer expansion and so gets split too.
+    fn expand_nested(
+        &mut self,
+        text: &str,
+        quoted: bool,
+        out: &mut Fields,
+    ) -> Result<(), CommandError> {
+        let mut nested = Fields::default();
+        self.expand(text, quoted, &mut nested)?;
+        out.keep |= nested.keep;
+        out.empty_at |= nested.empty_at;
+
+        let fields = nested.finish();
+        let count = fields.len();
+        for (i, (field, _)) in fields.into_iter().enumerate() {
+            out.current
+                .extend(field.into_iter().map(|(c, origin)| match origin {
+                    Origin::Literal => (c, Origin::Expanded),
+                    _ => (c, origin),
+                }));
+            if i + 1 < count {
+                out.split();
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands `text` on its own and removes quoting, joining fields.
+    fn expand_string(&mut self, text: &str, quoted: bool) -> Result<String, CommandError> {
+        let mut fields = Fields::default();
+        self.expand(text, quoted, &mut fields)?;
+        Ok(to_string(fields.finish()))
+    }
+
+    fn expand_pattern(&mut self, text: &str) -> Result<String, CommandError> {
+        let mut fields = Fields::default();
+        self.expand(text, false, &mut fields)?;
+        Ok(to_pattern(fields.finish()))
+    }
+
+    /// `${...}`, `inner` being the text between the braces.
+    fn braced(&mut self, inner: &str, quoted: bool, out: &mut Fields) -> Result<(), CommandError> {
+        let (length, expr) = match inner.strip_prefix('#') {
+            Some(rest) if !rest.is_empty() => (true, rest),
+            _ => (false, inner),
+        };
+
+        let name_len = match expr.chars().next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => expr
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(expr.len()),
+            Some(c) if c.is_ascii_digit() => expr
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(expr.len()),
+            Some('?' | '$' | '!' | '#' | '@' | '*') => 1,
+            _ => return Err(bad_substitution(inner)),
+        };
+        let (name, operation) = expr.split_at(name_len);
+        let value = self.lookup(name);
+
+        if length {
+            if !operation.is_empty() {
+                return Err(bad_substitution(inner));
+            }
+            let len = value.unwrap_or_default().chars().count();
+            self.insert("", Some(len.to_string()), quoted, out);
+            return Ok(());
+        }
+
+        let (colon, operation) = match operation.strip_prefix(':') {
+            Some(rest) => (true, rest),
+            None => (false, operation),
+        };
+        let missing = match &value {
+            None => true,
+            Some(value) => colon && value.is_empty(),
+        };
+        let mut op = operation.chars();
+        let Some(symbol) = op.next() else {
+            if colon {
+                return Err(bad_substitution(inner));
+            }
+            self.insert(name, value, quoted, out);
+            return Ok(());
+        };
+        let word = op.as_str();
+
+        match symbol {
+            '-' if missing => self.expand_nested(word, quoted, out)?,
+            '=' if missing => {
+                if !is_valid_name(name) {
+                    return Err(CommandError::Custom {
+                        prog_name: "dsh".into(),
+                        message: format!("${name}: cannot assign in this way"),
+                        status: 1,
+                    });
+                }
+                let value = self.expand_string(word, quoted)?;
+                self.executor.vars.set(name, value.clone())?;
+                self.insert(name, Some(value), quoted, out);
+            }
+            '?' if missing => {
+                let message = if word.is_empty() {
+                    "parameter null or not set".into()
+                } else {
+                    self.expand_string(word, quoted)?
+                };
+                return Err(CommandError::Custom {
+                    prog_name: name.into(),
+                    message,
+                    status: 1,
+                });
+            }
+            '+' if !missing => self.expand_nested(word, quoted, out)?,
+            '+' => {}
+            '-' | '=' | '?' => self.insert(name, value, quoted, out),
+            _ if colon => return Err(bad_substitution(inner)),
+            '#' | '%' => {
+                let (longest, word) = match word.strip_prefix(symbol) {
+                    Some(word) => (true, word),
+                    None => (false, word),
+                };
+                let pattern = self.expand_pattern(word)?;
+                let value = value.unwrap_or_default();
+                let result = if symbol == '#' {
+                    remove_prefix(&value, &pattern, longest)
+                } else {
+                    remove_suffix(&value, &pattern, longest)
+                };
+                self.insert("", Some(result), quoted, out);
+            }
+            '/' => {
+                let (mode, word) = match word.chars().next() {
+                    Some('/') => (Replace::All, &word[1..]),
+                    Some('#') => (Replace::Prefix, &word[1..]),
+                    Some('%') => (Replace::Suffix, &word[1..]),
+                    _ => (Replace::First, word),
+                };
+                let (pattern, replacement) = split_replacement(word);
+                let pattern = self.expand_pattern(pattern)?;
+                let replacement = self.expand_string(replacement, quoted)?;
+                let value = value.unwrap_or_default();
+                let result = replace(&value, &pattern, &replacement, mode);
+                self.insert("", Some(result), quoted, out);
+            }
+            _ => return Err(bad_substitution(inner)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Expands a word into the fields it stands for: parameters and commands are
+/// expanded, unquoted results are split on `$IFS`, patterns are replaced by
+/// the paths they match and quoting is removed.
+pub fn expand_word(
+    executor: &mut Executor,
+    override_maps: &HashMap<String, String>,
+    word: &str,
+) -> Result<Vec<String>, CommandError> {
+    let mut expander = Expander {
+        executor,
+        overrides: override_maps,
+        heredoc: false,
+        assignment: false,
+    };
+    let mut fields = Fields::default();
+    expander.expand(word, false, &mut fields)?;
+    let ifs = expander.ifs();
+
+    let options = expander.executor.glob;
+    let mut words = vec![];
+    for field in split_fields(fields.finish(), &ifs) {
+        let pattern = field_pattern(&field);
+        if !is_pattern(&pattern) {
+            words.push(field_text(&field));
+            continue;
+        }
+
+        let paths = glob(&pattern, options);
+        if !paths.is_empty() {
+            words.extend(paths);
+            continue;
+        }
+        match options.no_match {
+            NoMatch::Keep => words.push(field_text(&field)),
+            NoMatch::Drop => {}
+            NoMatch::Fail => {
+                return Err(CommandError::Custom {
+                    prog_name: "dsh".into(),
+                    message: format!("no matches found: {}", field_text(&field)),
+                    status: 1,
+                })
+            }
+        }
+    }
+
+    Ok(words)
+}
+
+/// Expands parameter references and removes quoting from a raw word, as a
+/// single string.
+pub fn expand_vars_into_arg(
+    executor: &mut Executor,
+    override_maps: &HashMap<String, String>,
+    arg: &str,
+) -> Result<String, CommandError> {
+    let mut expander = Expander {
+        executor,
+        overrides: override_maps,
+        heredoc: false,
+        assignment: false,
+    };
+    expander.expand_string(arg, false)
+}
+
+/// Expands the value of a `NAME=value` assignment: like a single word, with
+/// tilde expansion after every `:` as well, as in `PATH=~/bin:~/.local/bin`.
+pub fn expand_assignment(
+    executor: &mut Executor,
+    override_maps: &HashMap<String, String>,
+    value: &str,
+) -> Result<String, CommandError> {
+    let mut expander = Expander {
+        executor,
+        overrides: override_maps,
+        heredoc: false,
+        assignment: true,
+    };
+    expander.expand_string(value, false)
+}
+
+/// Expands the body of a here-document: quotes are kept as they are and only
+/// `\$`, `` \` ``, `\\` and `\<newline>` are escapes.
+pub fn expand_heredoc(
+    executor: &mut Executor,
+    override_maps: &HashMap<String, String>,
+    body: &str,
+) -> Result<String, CommandError> {
+    let mut expander = Expander {
+        executor,
+        overrides: override_maps,
+        heredoc: true,
+        assignment: false,
+    };
+    expander.expand_string(body, true)
+}
+
+/// Expands an `(( ))` expression as if it were double-quoted and evaluates
+/// it.
+pub fn expand_arithmetic(executor: &mut Executor, expr: &str) -> Result<i64, CommandError> {
+    let mut expander = Expander {
+        executor,
+        overrides: &HashMap::new(),
+        heredoc: false,
+        assignment: false,
+    };
+    let expr = expander.expand_string(expr, true)?;
+    arith::evaluate(&expr, &mut expander.executor.vars)
+}
+
+/// Expands a `case` pattern, quoted characters being escaped so that they
+/// only match themselves.
+pub fn expand_case_pattern(executor: &mut Executor, pattern: &str) -> Result<String, CommandError> {
+    let mut expander = Expander {
+        executor,
+        overrides: &HashMap::new(),
+        heredoc: false,
+        assignment: false,
+    };
+    expander.expand_pattern(pattern)
+}
+
+#[test]
+fn test_expand_var_simple() {
+    let mut overridemap = HashMap::new();
+    overridemap.insert("VAR".to_string(), "Nothing".to_string());
+    let new_arg = expand_vars_into_arg(&mut Executor::new(), &overridemap, "$VAR").unwrap();
+
+    assert_eq!(new_arg, "Nothing".to_string());
+}
+
+#[test]
+fn test_expand_var_double() {
+    let mut overridemap = HashMap::new();
+    overridemap.insert("VAR".to_string(), "Nothing".to_string());
+    let new_arg = expand_vars_into_arg(&mut Executor::new(), &overridemap, "$VAR$VAR").unwrap();
+
+    assert_eq!(new_arg, "NothingNothing".to_string());
+}
+
+#[test]
+fn test_expand_var_escape() {
+    let mut overridemap = HashMap::new();
+    overridemap.insert("VAR".to_string(), "Nothing".to_string());
+    let new_arg = expand_vars_into_arg(&mut Executor::new(), &overridemap, "$VAR\\$VAR").unwrap();
+
+    assert_eq!(new_arg, "Nothing$VAR".to_string());
+}
+
+#[test]
+fn test_expand_var_quotes() {
+    let mut overridemap = HashMap::new();
+    overridemap.insert("VAR".to_string(), "Nothing".to_string());
+    let new_arg =
+        expand_vars_into_arg(&mut Executor::new(), &overridemap, "'$VAR'\"-$VAR\"").unwrap();
+
+    assert_eq!(new_arg, "$VAR-Nothing".to_string());
+}
+
+#[test]
+fn test_expand_heredoc() {
+    let mut overridemap = HashMap::new();
+    overridemap.insert("VAR".to_string(), "Nothing".to_string());
+    let body = expand_heredoc(
+        &mut Executor::new(),
+        &overridemap,
+        "'$VAR' \"\\$VAR\" \\n\n",
+    )
+    .unwrap();
+
+    assert_eq!(body, "'Nothing' \"$VAR\" \\n\n".to_string());
+}
+
+#[test]
+fn test_pattern_matches() {
+    assert!(pattern_matches("*.rs", "main.rs"));
+    assert!(pattern_matches("a?c", "abc"));
+    assert!(pattern_matches("[a-c]x[!0-9]", "bxz"));
+    assert!(pattern_matches("[[:digit:]]*", "1st"));
+    assert!(pattern_matches("\\*", "*"));
+    assert!(!pattern_matches("\\*", "a"));
+    assert!(!pattern_matches("*.rs", "main.rsx"));
+    assert!(pattern_matches("[ab", "[ab"));
+}
+
+#[test]
+fn test_expand_parameters() {
+    let mut executor = Executor::new();
+    executor
+        .vars
+        .set("FILE", "dir/archive.tar.gz".into())
+        .unwrap();
+    executor.vars.set("EMPTY", "".into()).unwrap();
+    executor.positional = vec!["a b".into(), "c".into()];
+    let none = HashMap::new();
+    let mut expand = |word: &str| expand_word(&mut executor, &none, word).unwrap();
+
+    assert_eq!(expand("${FILE}"), ["dir/archive.tar.gz"]);
+    assert_eq!(expand("${#FILE}"), ["18"]);
+    assert_eq!(expand("${FILE#*.}"), ["tar.gz"]);
+    assert_eq!(expand("${FILE##*.}"), ["gz"]);
+    assert_eq!(expand("${FILE%.*}"), ["dir/archive.tar"]);
+    assert_eq!(expand("${FILE%%.*}"), ["dir/archive"]);
+    assert_eq!(expand("${FILE/a/A}"), ["dir/Archive.tar.gz"]);
+    assert_eq!(expand("${FILE//a/A}"), ["dir/Archive.tAr.gz"]);
+    assert_eq!(expand("${FILE/#dir/top}"), ["top/archive.tar.gz"]);
+    assert_eq!(expand("${FILE/%gz/xz}"), ["dir/archive.tar.xz"]);
+    assert_eq!(expand("${EMPTY:-x y}"), ["x", "y"]);
+    assert_eq!(expand("\"${EMPTY:-x y}\""), ["x y"]);
+    assert_eq!(expand("${EMPTY-x}"), [""; 0]);
+    assert_eq!(expand("${FILE:+set}"), ["set"]);
+    assert_eq!(expand("${UNSET_DSH_VAR:=new}"), ["new"]);
+    assert_eq!(expand("$#"), ["2"]);
+    assert_eq!(expand("$@"), ["a", "b", "c"]);
+    assert_eq!(expand("\"$@\""), ["a b", "c"]);
+    assert_eq!(expand("\"$*\""), ["a b c"]);
+    assert_eq!(expand("x$1y"), ["xa", "by"]);
+    assert_eq!(expand("${00}"), ["dsh"]);
+    assert_eq!(expand("${02}"), ["c"]);
+    assert_eq!(expand("${010}"), [""; 0]);
+    assert_eq!(expand("''"), [""]);
+    assert_eq!(expand("$EMPTY"), [""; 0]);
+    assert_eq!(executor.vars.get("UNSET_DSH_VAR"), Some("new"));
+
+    assert!(expand_word(&mut executor, &none, "${EMPTY:?is empty}").is_err());
+    assert!(expand_word(&mut executor, &none, "${FILE:x}").is_err());
+
+    executor.positional.clear();
+    let mut expand = |word: &str| expand_word(&mut executor, &none, word).unwrap();
+    assert_eq!(expand("\"$@\""), [""; 0]);
+}
+
+#[test]
+fn test_expand_command_substitution() {
+    let mut executor = Executor::new();
+    let none = HashMap::new();
+    let mut expand = |word: &str| expand_word(&mut executor, &none, word).unwrap();
+
+    assert_eq!(expand("$(printf 'a b\\n\\n')"), ["a", "b"]);
+    assert_eq!(expand("\"$(printf 'a b\\n\\n')\""), ["a b"]);
+    assert_eq!(expand("x`echo y`z"), ["xyz"]);
+    assert_eq!(expand("$(echo $(echo nested))"), ["nested"]);
+    assert_eq!(expand("\"$(echo \"a  b\")\""), ["a  b"]);
+    assert_eq!(expand("\"`echo \\\"q\\\"`\""), ["q"]);
+    assert_eq!(expand("$(true)"), [""; 0]);
+    assert_eq!(expand("$((1 + $(echo 2) * 3))"), ["7"]);
+    assert_eq!(expand("$(( (1 + 2) * 3 ))"), ["9"]);
+    assert_eq!(expand("$((n = 4)):$((n * n))"), ["4:16"]);
+    assert!(expand_word(&mut executor, &none, "$((1 / 0))").is_err());
+}
+
+#[test]
+fn test_expand_tilde() {
+    let mut executor = Executor::new();
+    executor.vars.set("HOME", "/home/dsh".into()).unwrap();
+    executor.vars.set("OLDPWD", "/old".into()).unwrap();
+    let none = HashMap::new();
+    let root_home = User::from_name("root").unwrap().unwrap().dir;
+    let mut expand = |word: &str| expand_word(&mut executor, &none, word).unwrap();
+
+    assert_eq!(expand("~"), ["/home/dsh"]);
+    assert_eq!(expand("~/x"), ["/home/dsh/x"]);
+    assert_eq!(expand("~-"), ["/old"]);
+    assert_eq!(expand("~root/x"), [format!("{}/x", root_home.display())]);
+    assert_eq!(expand("foo~bar"), ["foo~bar"]);
+    assert_eq!(expand("'~'"), ["~"]);
+    assert_eq!(expand("\\~"), ["~"]);
+    assert_eq!(expand("~'x'"), ["~x"]);
+    assert_eq!(expand("~dsh-no-such-user"), ["~dsh-no-such-user"]);
+    assert_eq!(expand("a:~"), ["a:~"]);
+
+    let value = expand_assignment(&mut executor, &none, "~/bin:~/sbin:a~").unwrap();
+    assert_eq!(value, "/home/dsh/bin:/home/dsh/sbin:a~");
+}
@@ -0,0 +1,181 @@
+use std::{fs, path::Path};
+
+use crate::expand::{is_pattern, pattern_matches};
+
+/// What becomes of a word whose pattern matches no file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoMatch {
+    /// Keep the pattern itself, as POSIX shells do.
+    #[default]
+    Keep,
+    /// Fail the command, like zsh or bash's `failglob`.
+    Fail,
+    /// Remove the word, like bash's `nullglob`.
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobOptions {
+    pub no_match: NoMatch,
+    /// Whether wildcards match a leading `.` too.
+    pub dotglob: bool,
+}
+
+/// Removes the backslashes of a pattern component without wildcards.
+fn unescape(component: &str) -> String {
+    let mut text = String::new();
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+fn join(base: &str, name: &str) -> String {
+    if base.is_empty() {
+        name.to_string()
+    } else if base.ends_with('/') {
+        format!("{base}{name}")
+    } else {
+        format!("{base}/{name}")
+    }
+}
+
+/// Names in `dir` along with whether they are directories, symbolic links
+/// not being followed.
+fn entries(dir: &str) -> Vec<(String, bool)> {
+    let path = if dir.is_empty() { "." } else { dir };
+    let Ok(entries) = fs::read_dir(path) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = entry.file_type().ok()?.is_dir();
+            Some((name, is_dir))
+        })
+        .collect()
+}
+
+/// `dir` and every directory below it, for `**`.
+fn subdirs(dir: &str, options: GlobOptions, out: &mut Vec<String>) {
+    out.push(dir.to_string());
+    for (name, is_dir) in entries(dir) {
+        if is_dir && (options.dotglob || !name.starts_with('.')) {
+            subdirs(&join(dir, &name), options, out);
+        }
+    }
+}
+
+/// Paths matching `pattern`, sorted. Wildcards never match `/`, nor a
+/// leading `.` unless the pattern has one or `dotglob` is set. A `**`
+/// component matches any number of directories.
+pub fn glob(pattern: &str, options: GlobOptions) -> Vec<String> {
+    let (pattern, dirs_only) = match pattern.strip_suffix('/') {
+        Some(stripped) if !stripped.is_empty() => (stripped, true),
+        _ => (pattern, false),
+    };
+    let visible = |name: &str, component: &str| {
+        !name.starts_with('.')
+            || options.dotglob
+            || component.starts_with('.')
+            || component.starts_with("\\.")
+    };
+
+    let root = if pattern.starts_with('/') { "/" } else { "" };
+    let mut paths = vec![root.to_string()];
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i + 1 == components.len();
+        let mut next = vec![];
+        for base in &paths {
+            if *component == "**" {
+                let mut dirs = vec![];
+                subdirs(base, options, &mut dirs);
+                if !is_last {
+                    next.extend(dirs);
+                    continue;
+                }
+                for dir in dirs {
+                    for (name, _) in entries(&dir) {
+                        if visible(&name, component) {
+                            next.push(join(&dir, &name));
+                        }
+                    }
+                }
+            } else if !is_pattern(component) {
+                next.push(join(base, &unescape(component)));
+            } else {
+                for (name, _) in entries(base) {
+                    if visible(&name, component) && pattern_matches(component, &name) {
+                        next.push(join(base, &name));
+                    }
+                }
+            }
+        }
+        paths = next;
+    }
+
+    paths.retain(|path| {
+        let path = Path::new(path);
+        path.symlink_metadata().is_ok() && (!dirs_only || path.is_dir())
+    });
+    if dirs_only {
+        for path in &mut paths {
+            path.push('/');
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+#[test]
+fn test_glob() {
+    let dir = std::env::temp_dir().join(format!("dsh-glob-{}", std::process::id()));
+    for path in [
+        "a.rs",
+        "b.rs",
+        "c.txt",
+        ".hidden.rs",
+        "sub/d.rs",
+        "sub/deep/e.rs",
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+    let base = dir.display().to_string();
+    let options = GlobOptions::default();
+    let glob = |pattern: &str, options| -> Vec<String> {
+        glob(&format!("{base}/{pattern}"), options)
+            .into_iter()
+            .map(|path| path[base.len() + 1..].to_string())
+            .collect()
+    };
+
+    assert_eq!(glob("*.rs", options), ["a.rs", "b.rs"]);
+    assert_eq!(glob("[!a].*", options), ["b.rs", "c.txt"]);
+    assert_eq!(glob("?.t*", options), ["c.txt"]);
+    assert_eq!(glob(".*.rs", options), [".hidden.rs"]);
+    assert_eq!(glob("*/", options), ["sub/"]);
+    assert_eq!(glob("*/*.rs", options), ["sub/d.rs"]);
+    assert_eq!(
+        glob("**/*.rs", options),
+        ["a.rs", "b.rs", "sub/d.rs", "sub/deep/e.rs"]
+    );
+    assert_eq!(glob("*.none", options), [""; 0]);
+
+    let options = GlobOptions {
+        dotglob: true,
+        ..options
+    };
+    assert_eq!(glob("*.rs", options), [".hidden.rs", "a.rs", "b.rs"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
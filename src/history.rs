@@ -0,0 +1,640 @@
+use std::{
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{error::CommandError, vars::Variables};
+
+/// How many entries are kept when `HISTSIZE` is unset or not a number.
+pub const DEFAULT_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Seconds since the epoch, 0 for lines read from a file without
+    /// timestamps.
+    pub time: u64,
+    pub line: String,
+}
+
+/// Commands entered in interactive sessions, oldest first. The file is
+/// written as bash does with `HISTTIMEFORMAT` set, each entry preceded by a
+/// `#<seconds>` line, so that entries may span several lines.
+#[derive(Debug)]
+pub struct History {
+    entries: Vec<Entry>,
+    pub size: usize,
+    /// `None` when history is only kept in memory.
+    pub file: Option<PathBuf>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn timestamp(line: &str) -> Option<u64> {
+    line.strip_prefix('#')
+        .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|digits| digits.parse().ok())
+}
+
+/// Reads entries back from the history file format. Lines before the first
+/// timestamp are entries of their own.
+pub fn parse(text: &str) -> Vec<Entry> {
+    let mut entries = vec![];
+    let mut current: Option<Entry> = None;
+    for line in text.lines() {
+        if let Some(time) = timestamp(line) {
+            entries.extend(current.take().filter(|entry| !entry.line.is_empty()));
+            current = Some(Entry {
+                time,
+                line: String::new(),
+            });
+            continue;
+        }
+        match &mut current {
+            Some(entry) => {
+                if !entry.line.is_empty() {
+                    entry.line.push('\n');
+                }
+                entry.line.push_str(line);
+            }
+            None if !line.is_empty() => entries.push(Entry {
+                time: 0,
+                line: line.to_string(),
+            }),
+            None => {}
+        }
+    }
+    entries.extend(current.filter(|entry| !entry.line.is_empty()));
+    entries
+}
+
+/// Formats `time` with `strftime`, in local time.
+pub fn format_time(time: u64, format: &str) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let time = time as libc::time_t;
+    let mut buf = [0u8; 256];
+    let len = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&time, &mut tm);
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Takes an advisory lock on the whole file, released when it is closed, so
+/// that sessions sharing a history file don't mix up their writes.
+fn lock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    match unsafe { libc::flock(file.as_raw_fd(), operation) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Reads the entries of a history file, a missing file having none.
+pub fn read_file(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    lock(&file, libc::LOCK_SH)?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    Ok(parse(&text))
+}
+
+/// Replaces the contents of a history file with `entries`.
+pub fn write_file(path: &Path, entries: &[Entry]) -> io::Result<()> {
+    // Only emptied once the lock is held.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    lock(&file, libc::LOCK_EX)?;
+    file.set_len(0)?;
+    file.write_all(format(entries).as_bytes())
+}
+
+/// Adds `entries` at the end of a history file, in a single write.
+pub fn append_file(path: &Path, entries: &[Entry]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    lock(&file, libc::LOCK_EX)?;
+    file.write_all(format(entries).as_bytes())
+}
+
+pub fn format(entries: &[Entry]) -> String {
+    entries
+        .iter()
+        .map(|entry| format!("#{}\n{}\n", entry.time, entry.line))
+        .collect()
+}
+
+fn event_error(spec: &[char], message: &str) -> CommandError {
+    CommandError::Custom {
+        prog_name: spec.iter().collect(),
+        message: message.into(),
+        status: 1,
+    }
+}
+
+fn is_operator(c: char) -> bool {
+    matches!(c, ';' | '&' | '|' | '<' | '>' | '(' | ')')
+}
+
+/// Characters that end the prefix of a `!prefix` reference.
+fn ends_prefix(c: char) -> bool {
+    c.is_whitespace() || c == ':' || is_operator(c) || matches!(c, '"' | '\'' | '`')
+}
+
+/// Splits `line` into the words designators count: quoted text stays in its
+/// word and runs of operators are words of their own.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut operator = false;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            word.push(c);
+            if c == q {
+                quote = None;
+            } else if c == '\\' && q == '"' {
+                word.extend(chars.next());
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        if !word.is_empty() && is_operator(c) != operator {
+            words.push(std::mem::take(&mut word));
+        }
+        operator = is_operator(c);
+        word.push(c);
+        match c {
+            '\\' => word.extend(chars.next()),
+            '\'' | '"' | '`' => quote = Some(c),
+            _ => {}
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn number(chars: &[char], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+        *i += 1;
+    }
+    chars[start..*i].iter().collect::<String>().parse().ok()
+}
+
+/// Reads up to `delimiter` or the end of the line. A backslash quotes the
+/// delimiter.
+fn delimited(chars: &[char], i: &mut usize, delimiter: char) -> String {
+    let mut text = String::new();
+    while let Some(&c) = chars.get(*i) {
+        *i += 1;
+        if c == '\\' && chars.get(*i) == Some(&delimiter) {
+            text.push(delimiter);
+            *i += 1;
+        } else if c == delimiter {
+            break;
+        } else {
+            text.push(c);
+        }
+    }
+    text
+}
+
+/// Replaces `old` by `new` in `text`, an `&` in `new` standing for `old`.
+fn substitute(text: &str, old: &str, new: &str, global: bool) -> Option<String> {
+    if old.is_empty() || !text.contains(old) {
+        return None;
+    }
+    let new = new.replace('&', old);
+    Some(match global {
+        true => text.replace(old, &new),
+        false => text.replacen(old, &new, 1),
+    })
+}
+
+/// Where the suffix of the last path component starts.
+fn suffix(text: &str) -> Option<usize> {
+    text.rfind('.').filter(|&dot| !text[dot..].contains('/'))
+}
+
+/// Selects words of `line` with the designator at `*i`: `n`, `^`, `$`, `*`,
+/// `x-y`, `-y`, `x-` (up to the last but one) or `x*`.
+fn select_words(
+    line: &str,
+    chars: &[char],
+    i: &mut usize,
+    start: usize,
+) -> Result<String, CommandError> {
+    let words = split_words(line);
+    let last = words.len().saturating_sub(1);
+    let first = match chars[*i] {
+        '*' => {
+            *i += 1;
+            return Ok(words.get(1..).unwrap_or_default().join(" "));
+        }
+        '$' => {
+            *i += 1;
+            return Ok(words.last().cloned().unwrap_or_default());
+        }
+        '^' => {
+            *i += 1;
+            1
+        }
+        '-' => 0,
+        _ => number(chars, i).unwrap_or(usize::MAX),
+    };
+    let end = match chars.get(*i) {
+        Some('*') => {
+            *i += 1;
+            last
+        }
+        Some('-') => {
+            *i += 1;
+            match chars.get(*i) {
+                Some('$') => {
+                    *i += 1;
+                    last
+                }
+                Some(c) if c.is_ascii_digit() => number(chars, i).unwrap_or(usize::MAX),
+                _ => last.saturating_sub(1),
+            }
+        }
+        _ => first,
+    };
+    words
+        .get(first..=end)
+        .map(|words| words.join(" "))
+        .ok_or_else(|| event_error(&chars[start..*i], "bad word specifier"))
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![],
+            size: DEFAULT_SIZE,
+            file: None,
+        }
+    }
+
+    /// Takes the size from `HISTSIZE` and the file from `HISTFILE`, which
+    /// defaults to `~/.dsh_history`. An empty `HISTFILE` disables the file.
+    pub fn configure(&mut self, vars: &Variables) {
+        self.size = vars
+            .get("HISTSIZE")
+            .and_then(|size| size.trim().parse().ok())
+            .unwrap_or(DEFAULT_SIZE);
+        self.file = match vars.get("HISTFILE") {
+            Some("") => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => vars
+                .get("HOME")
+                .map(|home| PathBuf::from(home).join(".dsh_history")),
+        };
+        self.trim();
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Entry> {
+        self.entries.get(index)
+    }
+
+    fn trim(&mut self) {
+        let excess = self.entries.len().saturating_sub(self.size);
+        self.entries.drain(..excess);
+    }
+
+    /// Removes every entry, the file is left as it is.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Entry> {
+        (index < self.entries.len()).then(|| self.entries.remove(index))
+    }
+
+    /// Replaces the entries with those of the file. A file grown past the
+    /// size is rewritten with the newest ones.
+    pub fn load(&mut self) -> io::Result<()> {
+        let Some(path) = self.file.clone() else {
+            self.entries.clear();
+            return Ok(());
+        };
+        self.entries = read_file(&path)?;
+        if self.entries.len() > self.size {
+            self.trim();
+            self.write(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the entries of the file at `path` after those in memory, which
+    /// picks up what other sessions appended to a shared file.
+    pub fn read(&mut self, path: &Path) -> io::Result<()> {
+        self.entries.extend(read_file(path)?);
+        self.trim();
+        Ok(())
+    }
+
+    /// Overwrites the file at `path` with the entries in memory.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        write_file(path, &self.entries)
+    }
+
+    /// Records `line` and appends it to the file, unless it is blank or the
+    /// same as the previous entry.
+    pub fn add(&mut self, line: &str) -> io::Result<()> {
+        let line = line.trim_end();
+        if line.trim_start().is_empty() || self.entries.last().is_some_and(|last| last.line == line)
+        {
+            return Ok(());
+        }
+        let entry = Entry {
+            time: now(),
+            line: line.to_string(),
+        };
+        self.entries.push(entry.clone());
+        self.trim();
+
+        match &self.file {
+            Some(path) if self.size > 0 => append_file(path, &[entry]),
+            _ => Ok(()),
+        }
+    }
+
+    /// Performs csh-style history expansion on a line as it is typed, before
+    /// it is parsed. Returns `None` when the line refers to no history entry.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, CommandError> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut expanded = String::new();
+        let mut i = 0;
+        // `^old^new^` stands for `!!:s/old/new/`.
+        if chars.first() == Some(&'^') {
+            i = 1;
+            let old = delimited(&chars, &mut i, '^');
+            let new = delimited(&chars, &mut i, '^');
+            let last = self
+                .entries
+                .last()
+                .ok_or_else(|| event_error(&chars[..i], "event not found"))?;
+            expanded = substitute(&last.line, &old, &new, false)
+                .ok_or_else(|| event_error(&chars[..i], "substitution failed"))?;
+        }
+
+        let mut found = i > 0;
+        let (mut single, mut double) = (false, false);
+        while let Some(&c) = chars.get(i) {
+            match c {
+                '\\' if !single => {
+                    expanded.push(c);
+                    expanded.extend(chars.get(i + 1));
+                    i += 2;
+                    continue;
+                }
+                '\'' if !double => single = !single,
+                '"' if !single => double = !double,
+                // A `!` that could only start an empty prefix, as in `hi!;`
+                // or `!'x'`, stands for itself.
+                '!' if !single => match chars.get(i + 1) {
+                    None | Some('=') => {}
+                    Some(&next) if next != ':' && ends_prefix(next) => {}
+                    Some(_) => {
+                        let (text, next) = self.expand_reference(&chars, i)?;
+                        expanded.push_str(&text);
+                        i = next;
+                        found = true;
+                        continue;
+                    }
+                },
+                _ => {}
+            }
+            expanded.push(c);
+            i += 1;
+        }
+        Ok(found.then_some(expanded))
+    }
+
+    /// Expands the reference whose `!` is at `start`: the event, then an
+    /// optional word designator and modifiers. Returns the text and where the
+    /// rest of the line starts.
+    fn expand_reference(
+        &self,
+        chars: &[char],
+        start: usize,
+    ) -> Result<(String, usize), CommandError> {
+        let mut i = start + 1;
+        let index = match chars[i] {
+            '!' => {
+                i += 1;
+                self.len().checked_sub(1)
+            }
+            '$' | '^' | '*' | ':' => self.len().checked_sub(1),
+            '?' => {
+                i += 1;
+                let text = delimited(chars, &mut i, '?');
+                self.entries.iter().rposition(|e| e.line.contains(&text))
+            }
+            '-' if chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                i += 1;
+                number(chars, &mut i).and_then(|n| self.len().checked_sub(n))
+            }
+            c if c.is_ascii_digit() => number(chars, &mut i).and_then(|n| n.checked_sub(1)),
+            _ => {
+                let prefix_start = i;
+                while chars.get(i).is_some_and(|&c| !ends_prefix(c)) {
+                    i += 1;
+                }
+                let prefix: String = chars[prefix_start..i].iter().collect();
+                self.entries
+                    .iter()
+                    .rposition(|e| e.line.starts_with(&prefix))
+            }
+        };
+        let line = &index
+            .and_then(|index| self.entries.get(index))
+            .ok_or_else(|| event_error(&chars[start..i], "event not found"))?
+            .line;
+
+        let designator = match (chars.get(i), chars.get(i + 1)) {
+            (Some(':'), Some(&c)) if c.is_ascii_digit() || matches!(c, '^' | '$' | '*' | '-') => {
+                i += 1;
+                true
+            }
+            (Some('^' | '$' | '*'), _) => true,
+            _ => false,
+        };
+        let mut text = match designator {
+            true => select_words(line, chars, &mut i, start)?,
+            false => line.clone(),
+        };
+
+        while chars.get(i) == Some(&':') {
+            let global = chars.get(i + 1) == Some(&'g');
+            let modifier = i + 1 + global as usize;
+            match (global, chars.get(modifier)) {
+                (false, Some('h')) => {
+                    if let Some(slash) = text.rfind('/') {
+                        text.truncate(slash);
+                    }
+                }
+                (false, Some('t')) => {
+                    if let Some(slash) = text.rfind('/') {
+                        text.drain(..=slash);
+                    }
+                }
+                (false, Some('r')) => {
+                    if let Some(dot) = suffix(&text) {
+                        text.truncate(dot);
+                    }
+                }
+                (false, Some('e')) => {
+                    if let Some(dot) = suffix(&text) {
+                        text.drain(..dot);
+                    }
+                }
+                (_, Some('s')) if modifier + 1 < chars.len() => {
+                    let delimiter = chars[modifier + 1];
+                    i = modifier + 2;
+                    let old = delimited(chars, &mut i, delimiter);
+                    let new = delimited(chars, &mut i, delimiter);
+                    text = substitute(&text, &old, &new, global)
+                        .ok_or_else(|| event_error(&chars[start..i], "substitution failed"))?;
+                    continue;
+                }
+                _ => break,
+            }
+            i = modifier + 1;
+        }
+        Ok((text, i))
+    }
+}
+
+#[test]
+fn test_parse_history() {
+    let text = "ls\n#1700000000\necho one\n#1700000005\ncat <<EOF\nhi\nEOF\n";
+    let entries = parse(text);
+    let lines: Vec<&str> = entries.iter().map(|entry| entry.line.as_str()).collect();
+    assert_eq!(lines, ["ls", "echo one", "cat <<EOF\nhi\nEOF"]);
+    assert_eq!(entries[1].time, 1700000000);
+    assert_eq!(parse(&format(&entries[1..])), entries[1..]);
+}
+
+#[test]
+fn test_history_add() {
+    let path = std::env::temp_dir().join(format!("dsh-history-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut history = History::new();
+    history.file = Some(path.clone());
+    history.size = 2;
+
+    for line in ["ls", "ls  ", "  ", "pwd", "ls"] {
+        history.add(line).unwrap();
+    }
+    let lines: Vec<&str> = history.entries().iter().map(|e| e.line.as_str()).collect();
+    assert_eq!(lines, ["pwd", "ls"]);
+
+    // The file keeps everything appended until it is loaded again.
+    let mut reloaded = History::new();
+    reloaded.file = Some(path.clone());
+    reloaded.size = 2;
+    reloaded.load().unwrap();
+    assert_eq!(reloaded.entries(), history.entries());
+    assert_eq!(read_file(&path).unwrap().len(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(test)]
+fn history(lines: &[&str]) -> History {
+    let mut history = History::new();
+    for line in lines {
+        history.add(line).unwrap();
+    }
+    history
+}
+
+#[test]
+fn test_expand_history() {
+    let history = history(&[
+        "ls -l /usr/lib/libc.so.6",
+        "echo one two three",
+        "git commit -m 'a b' && make",
+    ]);
+    let expand = |line| history.expand(line).unwrap();
+
+    assert_eq!(expand("!!").as_deref(), Some("git commit -m 'a b' && make"));
+    assert_eq!(
+        expand("sudo !-3").as_deref(),
+        Some("sudo ls -l /usr/lib/libc.so.6")
+    );
+    assert_eq!(expand("!$ !^").as_deref(), Some("make commit"));
+    assert_eq!(expand("!2:*").as_deref(), Some("one two three"));
+    assert_eq!(expand("!ec:2-3").as_deref(), Some("two three"));
+    assert_eq!(expand("!ec:1-").as_deref(), Some("one two"));
+    assert_eq!(expand("!?-m?:3").as_deref(), Some("'a b'"));
+    assert_eq!(expand("!git:4*").as_deref(), Some("&& make"));
+    assert_eq!(expand("cd !1:$:h").as_deref(), Some("cd /usr/lib"));
+    assert_eq!(expand("!1:$:t:r").as_deref(), Some("libc.so"));
+    assert_eq!(expand("!1:$:e").as_deref(), Some(".6"));
+    assert_eq!(expand("!2:s/two/2/").as_deref(), Some("echo one 2 three"));
+    assert_eq!(expand("!2:gs/o/0").as_deref(), Some("ech0 0ne tw0 three"));
+    assert_eq!(
+        expand("^commit^push").as_deref(),
+        Some("git push -m 'a b' && make")
+    );
+
+    for line in [
+        "echo '!!'",
+        "echo \\!x",
+        "[ a != b ]",
+        "echo hi!",
+        "echo \"wow!\"",
+        "echo hi!;",
+        "(echo a!)",
+        "echo !'x'",
+        "echo !\"x\"",
+    ] {
+        assert_eq!(expand(line), None);
+    }
+    for line in ["!nope", "!9", "!-4", "!1:7", "!!:s/zz/y/", "^zz^y"] {
+        assert!(history.expand(line).is_err(), "{line}");
+    }
+}
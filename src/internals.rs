@@ -1,114 +1,947 @@
 use std::{
     collections::HashMap,
     env,
+    fs::{self, File, OpenOptions},
     io::{self, prelude::*},
-    os::unix::process::ExitStatusExt,
-    process::{Command, ExitStatus, Stdio},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::{CommandExt, ExitStatusExt},
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use crate::{cmd::Cmd, error::CommandError};
+use nix::fcntl::{fcntl, FcntlArg};
 
-pub type InternalFunc = fn(Cmd) -> Result<ExitStatus, CommandError>;
+use crate::{
+    cmd::{Cmd, Redirect, RedirectKind},
+    error::CommandError,
+    exec::{Executor, Jump},
+    glob::{GlobOptions, NoMatch},
+    history::format_time,
+    jobs::{self, ProcessGroup},
+    vars::{is_valid_name, Variable, Variables},
+};
+
+pub type InternalFunc = fn(&mut Executor, Cmd) -> Result<ExitStatus, CommandError>;
 pub type InternalFuncMap = HashMap<String, InternalFunc>;
 
-pub fn clear(_: Cmd) -> Result<ExitStatus, CommandError> {
+/// `ExitStatus` of a builtin that exited with `code`.
+pub fn exit_status(code: i32) -> ExitStatus {
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+pub fn clear(_: &mut Executor, _: Cmd) -> Result<ExitStatus, CommandError> {
     print!("\x1b[2J\x1b[H");
     io::stdout().flush().unwrap();
     Ok(ExitStatus::from_raw(0))
 }
 
-pub fn cd(Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
-    if args.is_empty() {
-        if let Ok(dir_path) = env::var("HOME") {
-            let new_cwd = std::path::Path::new(&dir_path);
-
-            if let Err(e) = env::set_current_dir(&new_cwd) {
-                let message = format!("{}", e);
-                Err(CommandError::Custom {
-                    prog_name: "cmd".into(),
-                    message,
-                    status: 1,
-                })
-            } else {
-                Ok(ExitStatus::from_raw(0))
+pub fn cd(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let dir_path = match args.first() {
+        Some(dir_path) => dir_path.clone(),
+        None => match executor.vars.get("HOME") {
+            Some(home) => home.to_string(),
+            None => {
+                eprintln!("cd: No Argument provided");
+                return Ok(exit_status(1));
             }
+        },
+    };
+    let new_cwd = std::path::Path::new(&dir_path);
+
+    if let Err(e) = env::set_current_dir(new_cwd) {
+        let message = format!("{}", e);
+        return Err(CommandError::Custom {
+            prog_name: "cd".into(),
+            message,
+            status: 1,
+        });
+    }
+
+    // `~+` and `~-` rely on these.
+    if let Some(old_pwd) = executor.vars.get("PWD").map(String::from) {
+        executor.vars.set("OLDPWD", old_pwd)?;
+    }
+    if let Ok(pwd) = env::current_dir() {
+        executor.vars.set("PWD", pwd.display().to_string())?;
+    }
+
+    Ok(ExitStatus::from_raw(0))
+}
+
+fn resolve_job(executor: &Executor, prog_name: &str, spec: &str) -> Result<usize, CommandError> {
+    executor
+        .jobs
+        .resolve(spec)
+        .ok_or_else(|| CommandError::Custom {
+            prog_name: prog_name.into(),
+            message: format!("{spec}: no such job"),
+            status: 1,
+        })
+}
+
+/// `jobs [-lp] [jobspec...]`
+pub fn jobs(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let mut long = false;
+    let mut pids_only = false;
+    let mut specs = vec![];
+    for arg in &args {
+        match arg.as_str() {
+            "-l" => long = true,
+            "-p" => pids_only = true,
+            "-lp" | "-pl" => (long, pids_only) = (true, true),
+            _ => specs.push(arg.as_str()),
+        }
+    }
+
+    executor.jobs.poll();
+    let ids = if specs.is_empty() {
+        executor.jobs.ids()
+    } else {
+        specs
+            .iter()
+            .map(|spec| resolve_job(executor, "jobs", spec))
+            .collect::<Result<_, _>>()?
+    };
+
+    for id in &ids {
+        let Some(job) = executor.jobs.get(*id) else {
+            continue;
+        };
+        if pids_only {
+            println!("{}", job.pgid);
+        } else if long {
+            let pids: Vec<String> = job
+                .processes
+                .iter()
+                .filter_map(|p| p.pid.map(|pid| pid.to_string()))
+                .collect();
+            println!("{} [{}]", executor.jobs.format(job), pids.join(" "));
         } else {
-            eprintln!("cd: No Argument provided");
-            Ok(ExitStatus::from_raw(1))
+            println!("{}", executor.jobs.format(job));
         }
+    }
+
+    // Finished jobs are reported once.
+    for id in ids {
+        if executor.jobs.get(id).is_some_and(|job| job.is_done()) {
+            executor.jobs.remove(id);
+        }
+    }
+
+    Ok(exit_status(0))
+}
+
+/// `fg [jobspec]`
+pub fn fg(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let spec = args.first().map_or("%%", String::as_str);
+    let id = resolve_job(executor, "fg", spec)?;
+
+    Ok(exit_status(executor.resume_job(id, true)))
+}
+
+/// `bg [jobspec...]`
+pub fn bg(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let specs = if args.is_empty() {
+        vec!["%%".to_string()]
     } else {
-        let mut dir_path = args[0].clone();
-        if dir_path.contains('~') {
-            if let Ok(home) = env::var("HOME") {
-                dir_path = dir_path.replace("~", &home);
+        args
+    };
+
+    let mut status = 0;
+    for spec in specs {
+        let id = resolve_job(executor, "bg", &spec)?;
+        status = executor.resume_job(id, false);
+    }
+
+    Ok(exit_status(status))
+}
+
+/// `disown [-a] [jobspec...]`
+pub fn disown(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let ids = if args.iter().any(|arg| arg == "-a") {
+        executor.jobs.ids()
+    } else if args.is_empty() {
+        vec![resolve_job(executor, "disown", "%%")?]
+    } else {
+        args.iter()
+            .map(|spec| resolve_job(executor, "disown", spec))
+            .collect::<Result<_, _>>()?
+    };
+
+    for id in ids {
+        executor.jobs.remove(id);
+    }
+
+    Ok(exit_status(0))
+}
+
+/// Splits a `name[=value]` operand of `export`, `readonly` or `declare`.
+fn declared_var<'a>(
+    prog_name: &str,
+    arg: &'a str,
+) -> Result<(&'a str, Option<&'a str>), CommandError> {
+    let (name, value) = match arg.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (arg, None),
+    };
+    if !is_valid_name(name) {
+        return Err(CommandError::Custom {
+            prog_name: prog_name.into(),
+            message: format!("`{arg}': not a valid identifier"),
+            status: 1,
+        });
+    }
+
+    Ok((name, value))
+}
+
+/// Prints the `declare -p` line of every variable accepted by `filter`.
+fn print_declarations(executor: &Executor, filter: impl Fn(&Variable) -> bool) {
+    for name in executor.vars.names() {
+        if !executor.vars.attributes(name).is_some_and(&filter) {
+            continue;
+        }
+        if let Some(line) = executor.vars.declaration(name) {
+            println!("{line}");
+        }
+    }
+}
+
+/// `export [-np] [name[=value]...]`
+pub fn export(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let mut unexport = false;
+    let mut operands = vec![];
+    for arg in &args {
+        match arg.as_str() {
+            "-n" => unexport = true,
+            "-p" => {}
+            _ => operands.push(arg.as_str()),
+        }
+    }
+
+    if operands.is_empty() {
+        print_declarations(executor, |var| var.exported);
+        return Ok(exit_status(0));
+    }
+    for arg in operands {
+        let (name, value) = declared_var("export", arg)?;
+        if let Some(value) = value {
+            executor.vars.set(name, value.into())?;
+        }
+        executor.vars.set_exported(name, !unexport);
+    }
+
+    Ok(exit_status(0))
+}
+
+/// `readonly [-p] [name[=value]...]`
+pub fn readonly(
+    executor: &mut Executor,
+    Cmd { args, .. }: Cmd,
+) -> Result<ExitStatus, CommandError> {
+    let operands: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "-p")
+        .collect();
+
+    if operands.is_empty() {
+        print_declarations(executor, |var| var.readonly);
+        return Ok(exit_status(0));
+    }
+    for arg in operands {
+        let (name, value) = declared_var("readonly", arg)?;
+        if let Some(value) = value {
+            executor.vars.set(name, value.into())?;
+        }
+        executor.vars.set_readonly(name);
+    }
+
+    Ok(exit_status(0))
+}
+
+/// `unset [-fv] name...`
+pub fn unset(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let functions = args.iter().any(|arg| arg == "-f");
+    for name in args.iter().filter(|arg| *arg != "-f" && *arg != "-v") {
+        if functions {
+            executor.functions.remove(name);
+            continue;
+        }
+        declared_var("unset", name)?;
+        executor.vars.unset(name)?;
+    }
+
+    Ok(exit_status(0))
+}
+
+/// `local [name[=value]...]`
+pub fn local(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    for arg in &args {
+        let (name, value) = declared_var("local", arg)?;
+        executor.vars.make_local(name)?;
+        if let Some(value) = value {
+            executor.vars.set(name, value.into())?;
+        }
+    }
+
+    Ok(exit_status(0))
+}
+
+/// Prints the definition of the functions in `names`, or of every function
+/// when there are none, and returns 1 if one of them is not defined.
+fn print_functions(executor: &Executor, prog_name: &str, names: &[&str]) -> i32 {
+    let mut names = names.to_vec();
+    if names.is_empty() {
+        names = executor.functions.keys().map(String::as_str).collect();
+        names.sort_unstable();
+    }
+
+    let mut status = 0;
+    for name in names {
+        match executor.functions.get(name) {
+            Some(body) => println!("{name}() {body}"),
+            None => {
+                eprintln!("[{prog_name}]: {name}: not found");
+                status = 1;
             }
         }
-        let new_cwd = std::path::Path::new(&dir_path);
+    }
+    status
+}
 
-        if let Err(e) = env::set_current_dir(&new_cwd) {
-            let message = format!("{}", e);
-            Err(CommandError::Custom {
-                prog_name: "cmd".into(),
-                message,
-                status: 1,
-            })
-        } else {
-            Ok(ExitStatus::from_raw(0))
+/// `functions [name...]`
+pub fn functions(
+    executor: &mut Executor,
+    Cmd { args, .. }: Cmd,
+) -> Result<ExitStatus, CommandError> {
+    let names: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    Ok(exit_status(print_functions(executor, "functions", &names)))
+}
+
+/// Quotes `value` so that it reads back as the same word.
+fn single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// `alias [-p] [name[=value]...]`
+pub fn alias(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let operands: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "-p")
+        .collect();
+    let print = |name: &str, value: &str| println!("alias {name}={}", single_quote(value));
+
+    if operands.is_empty() {
+        let mut names: Vec<&String> = executor.aliases.keys().collect();
+        names.sort_unstable();
+        for name in names {
+            print(name, &executor.aliases[name]);
+        }
+        return Ok(exit_status(0));
+    }
+
+    let mut status = 0;
+    for arg in operands {
+        match arg.split_once('=') {
+            Some((name, value)) if !name.is_empty() => {
+                executor.aliases.insert(name.into(), value.into());
+            }
+            _ => match executor.aliases.get(arg) {
+                Some(value) => print(arg, value),
+                None => {
+                    eprintln!("[alias]: {arg}: not found");
+                    status = 1;
+                }
+            },
+        }
+    }
+
+    Ok(exit_status(status))
+}
+
+/// `unalias [-a] name...`
+pub fn unalias(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    if args.iter().any(|arg| arg == "-a") {
+        executor.aliases.clear();
+        return Ok(exit_status(0));
+    }
+
+    let mut status = 0;
+    for name in &args {
+        if executor.aliases.remove(name).is_none() {
+            eprintln!("[unalias]: {name}: not found");
+            status = 1;
         }
     }
+
+    Ok(exit_status(status))
+}
+
+/// `history [-c] [-d offset] [-r | -w] [count | pattern | file]`
+pub fn history(
+    executor: &mut Executor,
+    Cmd {
+        args,
+        variables_overrides,
+        ..
+    }: Cmd,
+) -> Result<ExitStatus, CommandError> {
+    let error = |message: String, status| CommandError::Custom {
+        prog_name: "history".into(),
+        message,
+        status,
+    };
+    let history = &mut executor.history;
+    let (mut edited, mut read, mut write) = (false, false, false);
+    let mut operands = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" => {
+                history.clear();
+                edited = true;
+            }
+            "-d" => {
+                let Some(offset) = args.next() else {
+                    return Err(error("-d: option requires an argument".into(), 2));
+                };
+                let removed = offset
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| n.checked_sub(1))
+                    .and_then(|index| history.remove(index));
+                if removed.is_none() {
+                    return Err(error(format!("{offset}: history position out of range"), 1));
+                }
+                edited = true;
+            }
+            "-r" => read = true,
+            "-w" => write = true,
+            "--" => operands.extend(args.by_ref()),
+            option if option.len() > 1 && option.starts_with('-') => {
+                return Err(error(format!("{option}: invalid option"), 2))
+            }
+            _ => operands.push(arg),
+        }
+    }
+    if operands.len() > 1 {
+        return Err(error("too many arguments".into(), 2));
+    }
+
+    if read || write {
+        let Some(path) = operands.first().map(PathBuf::from).or(history.file.clone()) else {
+            return Err(error("no history file".into(), 1));
+        };
+        let done = match read {
+            true => history.read(&path),
+            false => history.write(&path),
+        };
+        done.map_err(|e| error(format!("{}: {e}", path.display()), 1))?;
+        return Ok(exit_status(0));
+    }
+    if edited && operands.is_empty() {
+        return Ok(exit_status(0));
+    }
+
+    // A number lists that many of the last entries, anything else only
+    // those containing it.
+    let entries = executor.history.entries();
+    let (start, pattern) = match operands.first() {
+        Some(operand) => match operand.parse::<usize>() {
+            Ok(count) => (entries.len().saturating_sub(count), None),
+            Err(_) => (0, Some(operand.as_str())),
+        },
+        None => (0, None),
+    };
+    let format = match variables_overrides.get("HISTTIMEFORMAT") {
+        Some(format) => format,
+        None => executor.vars.get("HISTTIMEFORMAT").unwrap_or("%F %T  "),
+    };
+    for (i, entry) in entries.iter().enumerate().skip(start) {
+        if pattern.is_some_and(|pattern| !entry.line.contains(pattern)) {
+            continue;
+        }
+        let time = match entry.time {
+            0 => String::new(),
+            time => format_time(time, format),
+        };
+        println!("{:>5}  {time}{}", i + 1, entry.line);
+    }
+
+    Ok(exit_status(0))
+}
+
+/// Where `source name` reads from: `name` itself if it has a slash,
+/// otherwise the first match in `$PATH` or the current directory.
+fn sourced_path(executor: &Executor, name: &str) -> PathBuf {
+    if !name.contains('/') {
+        let path = executor.vars.get("PATH").unwrap_or_default();
+        for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+            let candidate = Path::new(dir).join(name);
+            if candidate.is_file() {
+                return candidate;
+            }
+        }
+    }
+    PathBuf::from(name)
+}
+
+/// `source file [arg...]`, also `. file [arg...]`
+pub fn source(
+    executor: &mut Executor,
+    Cmd { name, args, .. }: Cmd,
+) -> Result<ExitStatus, CommandError> {
+    let error = |message: String, status| CommandError::Custom {
+        prog_name: name.clone(),
+        message,
+        status,
+    };
+    let Some(file) = args.first() else {
+        return Err(error("filename argument required".into(), 2));
+    };
+    let script = fs::read_to_string(sourced_path(executor, file))
+        .map_err(|e| error(format!("{file}: {e}"), 1))?;
+
+    let positional =
+        (args.len() > 1).then(|| std::mem::replace(&mut executor.positional, args[1..].to_vec()));
+    executor.source_depth += 1;
+    let status = executor.run_script(&script);
+    executor.source_depth -= 1;
+    if executor.jump == Some(Jump::Return) {
+        executor.jump = None;
+    }
+    if let Some(positional) = positional {
+        executor.positional = positional;
+    }
+
+    Ok(exit_status(status))
+}
+
+/// `exit [n]`
+pub fn exit(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let status = match args.first() {
+        None => executor.last_status,
+        Some(arg) => arg.parse().map_err(|_| CommandError::Custom {
+            prog_name: "exit".into(),
+            message: format!("{arg}: numeric argument required"),
+            status: 2,
+        })?,
+    };
+    executor.jump = Some(Jump::Exit);
+
+    Ok(exit_status(status))
+}
+
+/// `return [n]`
+pub fn return_from(
+    executor: &mut Executor,
+    Cmd { args, .. }: Cmd,
+) -> Result<ExitStatus, CommandError> {
+    if executor.function_depth == 0 && executor.source_depth == 0 {
+        return Err(CommandError::Custom {
+            prog_name: "return".into(),
+            message: "can only `return' from a function or sourced script".into(),
+            status: 1,
+        });
+    }
+    let status = match args.first() {
+        None => executor.last_status,
+        Some(arg) => arg.parse().map_err(|_| CommandError::Custom {
+            prog_name: "return".into(),
+            message: format!("{arg}: numeric argument required"),
+            status: 2,
+        })?,
+    };
+    executor.jump = Some(Jump::Return);
+
+    Ok(exit_status(status))
+}
+
+/// `declare [-fprx] [name[=value]...]`
+pub fn declare(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let (mut print, mut readonly, mut exported) = (false, false, false);
+    let mut functions = false;
+    let mut operands = vec![];
+    for arg in &args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'f' => functions = true,
+                        'p' => print = true,
+                        'r' => readonly = true,
+                        'x' => exported = true,
+                        _ => {
+                            return Err(CommandError::Custom {
+                                prog_name: "declare".into(),
+                                message: format!("-{flag}: invalid option"),
+                                status: 2,
+                            })
+                        }
+                    }
+                }
+            }
+            _ => operands.push(arg.as_str()),
+        }
+    }
+
+    if functions {
+        return Ok(exit_status(print_functions(executor, "declare", &operands)));
+    }
+    if operands.is_empty() {
+        print_declarations(executor, |var| {
+            (!readonly || var.readonly) && (!exported || var.exported)
+        });
+        return Ok(exit_status(0));
+    }
+
+    let mut status = 0;
+    for arg in operands {
+        let (name, value) = declared_var("declare", arg)?;
+        if print {
+            match executor.vars.declaration(name) {
+                Some(line) => println!("{line}"),
+                None => {
+                    eprintln!("[declare]: {name}: not found");
+                    status = 1;
+                }
+            }
+            continue;
+        }
+
+        if let Some(value) = value {
+            executor.vars.set(name, value.into())?;
+        }
+        if exported {
+            executor.vars.set_exported(name, true);
+        }
+        if readonly {
+            executor.vars.set_readonly(name);
+        }
+    }
+
+    Ok(exit_status(status))
+}
+
+/// `shopt [-su] [optname...]`, for the `nullglob`, `failglob` and `dotglob`
+/// options.
+pub fn shopt(executor: &mut Executor, Cmd { args, .. }: Cmd) -> Result<ExitStatus, CommandError> {
+    let mut set = None;
+    let mut names = vec![];
+    for arg in &args {
+        match arg.as_str() {
+            "-s" => set = Some(true),
+            "-u" => set = Some(false),
+            _ => names.push(arg.as_str()),
+        }
+    }
+    if names.is_empty() {
+        names = vec!["dotglob", "failglob", "nullglob"];
+    }
+
+    let glob = &mut executor.glob;
+    for name in names {
+        let (enabled, on) = match name {
+            "dotglob" => (
+                glob.dotglob,
+                GlobOptions {
+                    dotglob: true,
+                    ..*glob
+                },
+            ),
+            "failglob" => (
+                glob.no_match == NoMatch::Fail,
+                GlobOptions {
+                    no_match: NoMatch::Fail,
+                    ..*glob
+                },
+            ),
+            "nullglob" => (
+                glob.no_match == NoMatch::Drop,
+                GlobOptions {
+                    no_match: NoMatch::Drop,
+                    ..*glob
+                },
+            ),
+            _ => {
+                return Err(CommandError::Custom {
+                    prog_name: "shopt".into(),
+                    message: format!("{name}: invalid shell option name"),
+                    status: 1,
+                })
+            }
+        };
+
+        match set {
+            Some(true) => *glob = on,
+            Some(false) if name == "dotglob" => glob.dotglob = false,
+            Some(false) if enabled => glob.no_match = NoMatch::Keep,
+            Some(false) => {}
+            None => println!("{name:<16}{}", if enabled { "on" } else { "off" }),
+        }
+    }
+
+    Ok(exit_status(0))
+}
+
+/// How many loops `break`/`continue` leave, capped to the ones it is in.
+fn loop_count(executor: &Executor, name: &str, args: &[String]) -> Result<usize, CommandError> {
+    let error = |message: String| CommandError::Custom {
+        prog_name: name.into(),
+        message,
+        status: 1,
+    };
+    let count = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            _ => return Err(error(format!("{arg}: loop count out of range"))),
+        },
+    };
+    if executor.loop_depth == 0 {
+        return Err(error(
+            "only meaningful in a `for', `while', or `until' loop".into(),
+        ));
+    }
+
+    Ok(count.min(executor.loop_depth))
+}
+
+/// `break [n]`
+pub fn break_loop(
+    executor: &mut Executor,
+    Cmd { args, .. }: Cmd,
+) -> Result<ExitStatus, CommandError> {
+    let count = loop_count(executor, "break", &args)?;
+    executor.jump = Some(Jump::Break(count));
+
+    Ok(exit_status(0))
+}
+
+/// `continue [n]`
+pub fn continue_loop(
+    executor: &mut Executor,
+    Cmd { args, .. }: Cmd,
+) -> Result<ExitStatus, CommandError> {
+    let count = loop_count(executor, "continue", &args)?;
+    executor.jump = Some(Jump::Continue(count));
+
+    Ok(exit_status(0))
 }
 
 pub fn get_internal_functions_map() -> InternalFuncMap {
     let mut map = InternalFuncMap::new();
     map.insert("clear".into(), clear);
     map.insert("cd".into(), cd);
+    map.insert("jobs".into(), jobs);
+    map.insert("fg".into(), fg);
+    map.insert("bg".into(), bg);
+    map.insert("disown".into(), disown);
+    map.insert("export".into(), export);
+    map.insert("readonly".into(), readonly);
+    map.insert("unset".into(), unset);
+    map.insert("declare".into(), declare);
+    map.insert("shopt".into(), shopt);
+    map.insert("break".into(), break_loop);
+    map.insert("continue".into(), continue_loop);
+    map.insert("local".into(), local);
+    map.insert("return".into(), return_from);
+    map.insert("exit".into(), exit);
+    map.insert("source".into(), source);
+    map.insert(".".into(), source);
+    map.insert("functions".into(), functions);
+    map.insert("alias".into(), alias);
+    map.insert("unalias".into(), unalias);
+    map.insert("history".into(), history);
 
     map
 }
 
-fn resetvars(overrides: HashMap<String, String>, before_run: env::Vars) {
-    for (k, _) in overrides {
-        env::remove_var(k);
+pub enum RedirectSource {
+    File(File),
+    Fd(RawFd),
+    Close,
+}
+
+/// Stores `content` in an unlinked temporary file so that it can be read back
+/// whatever its size.
+fn here_file(content: &str) -> io::Result<File> {
+    static HERE_FILES: AtomicUsize = AtomicUsize::new(0);
+
+    let path = env::temp_dir().join(format!(
+        "dsh-here-{}-{}",
+        std::process::id(),
+        HERE_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    std::fs::remove_file(&path)?;
+    file.write_all(content.as_bytes())?;
+    file.rewind()?;
+
+    Ok(file)
+}
+
+/// Moves `file` to a descriptor of at least 10, out of the way of those
+/// redirections target: `3>file` must not leave the file itself on fd 3 with
+/// close-on-exec set, or close it when the `File` is dropped.
+fn move_high(file: File) -> io::Result<File> {
+    let fd = fcntl(file.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(10)).map_err(io::Error::from)?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Opens every file named by `redirects`, in order, and returns what each
+/// target descriptor should end up pointing to.
+pub fn open_redirects(
+    redirects: &[Redirect],
+) -> Result<Vec<(RawFd, RedirectSource)>, CommandError> {
+    let mut plan = vec![];
+    for Redirect { fd, kind, target } in redirects {
+        let opened = match kind {
+            RedirectKind::Input => File::open(target),
+            RedirectKind::Output => File::create(target),
+            RedirectKind::Append => OpenOptions::new().append(true).create(true).open(target),
+            RedirectKind::HereDoc { .. } | RedirectKind::HereString => here_file(target),
+            RedirectKind::DupInput | RedirectKind::DupOutput => {
+                let source = if target == "-" {
+                    RedirectSource::Close
+                } else if let Ok(source) = target.parse() {
+                    RedirectSource::Fd(source)
+                } else {
+                    return Err(CommandError::Custom {
+                        prog_name: target.clone(),
+                        message: "ambiguous redirect".into(),
+                        status: 1,
+                    });
+                };
+                plan.push((*fd, source));
+                continue;
+            }
+        };
+
+        match opened.and_then(move_high) {
+            Ok(file) => plan.push((*fd, RedirectSource::File(file))),
+            Err(e) => {
+                let prog_name = match kind {
+                    RedirectKind::HereDoc { .. } | RedirectKind::HereString => "dsh".into(),
+                    _ => target.clone(),
+                };
+                return Err(CommandError::Custom {
+                    prog_name,
+                    message: format!("{e}"),
+                    status: 1,
+                });
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Installs `plan` on the current process. Only calls `dup2`/`close` so it is
+/// safe to use between `fork` and `exec`.
+fn apply_redirects(plan: &[(RawFd, RedirectSource)]) -> io::Result<()> {
+    for (fd, source) in plan {
+        let res = match source {
+            RedirectSource::File(file) => unsafe { libc::dup2(file.as_raw_fd(), *fd) },
+            RedirectSource::Fd(source) => unsafe { libc::dup2(*source, *fd) },
+            RedirectSource::Close => unsafe { libc::close(*fd) },
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Redirections applied to the shell itself while a builtin runs; the
+/// original descriptors come back when the guard is dropped.
+pub struct RedirectGuard {
+    saved: Vec<(RawFd, Option<OwnedFd>)>,
+}
+
+pub fn redirect(redirects: &[Redirect]) -> Result<RedirectGuard, CommandError> {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+
+    // Saved before any file is opened, which could otherwise take the place
+    // of a closed target.
+    let mut guard = RedirectGuard { saved: vec![] };
+    for Redirect { fd, .. } in redirects {
+        let saved = fcntl(*fd, FcntlArg::F_DUPFD_CLOEXEC(10))
+            .ok()
+            .map(|saved| unsafe { OwnedFd::from_raw_fd(saved) });
+        guard.saved.push((*fd, saved));
     }
+    let plan = open_redirects(redirects)?;
+    apply_redirects(&plan).map_err(CommandError::IOError)?;
 
-    for (k, v) in before_run {
-        env::set_var(k, v);
+    Ok(guard)
+}
+
+impl Drop for RedirectGuard {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        for (fd, saved) in self.saved.drain(..).rev() {
+            match saved {
+                Some(saved) => unsafe { libc::dup2(saved.as_raw_fd(), fd) },
+                None => unsafe { libc::close(fd) },
+            };
+        }
     }
 }
 
-pub fn run(
+/// Starts `cmd` without waiting for it, wiring its stdin/stdout as requested.
+/// The child's environment is the exported part of `vars` plus the command's
+/// own assignments.
+pub fn spawn(
     Cmd {
         variables_overrides,
         name,
         args,
+        redirects,
     }: Cmd,
-) -> Result<ExitStatus, CommandError> {
-    let previous_vars_state = env::vars();
+    vars: &Variables,
+    stdin: Stdio,
+    stdout: Stdio,
+    group: Option<ProcessGroup>,
+) -> Result<Child, CommandError> {
+    let plan = open_redirects(&redirects)?;
 
-    for (k, v) in &variables_overrides {
-        env::set_var(k, v);
-    }
-
-    match Command::new(name.as_str())
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
+    let mut command = Command::new(name.as_str());
+    command
+        .env_clear()
+        .envs(vars.exported())
+        .envs(&variables_overrides)
+        .stdin(stdin)
+        .stdout(stdout)
         .stderr(Stdio::inherit())
-        .args(args)
-        .spawn()
-    {
-        Ok(mut child) => match child.wait() {
-            Ok(status) => {
-                resetvars(variables_overrides, previous_vars_state);
-                return Ok(status);
+        .args(args);
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(group) = group {
+                jobs::join_group(group);
             }
-            Err(e) => {
-                resetvars(variables_overrides, previous_vars_state);
-                return Err(CommandError::ChildExit(e, 130));
-            }
-        },
-        Err(e) => {
-            resetvars(variables_overrides, previous_vars_state);
-            return Err(CommandError::ChildSpawnError(e, name, 127));
-        }
+            apply_redirects(&plan)
+        });
     }
+    command
+        .spawn()
+        .map_err(|e| CommandError::ChildSpawnError(e, name, 127))
+}
+
+pub fn run(cmd: Cmd, vars: &Variables) -> Result<ExitStatus, CommandError> {
+    let mut child = spawn(cmd, vars, Stdio::inherit(), Stdio::inherit(), None)?;
+    child.wait().map_err(|e| CommandError::ChildExit(e, 130))
 }
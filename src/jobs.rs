@@ -0,0 +1,343 @@
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{kill, killpg, Signal},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
+
+/// Process group a spawned process should join when job control is on.
+/// A `pgid` of 0 starts a new group led by the process itself.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessGroup {
+    pub pgid: i32,
+    pub foreground: bool,
+}
+
+/// Hands the controlling terminal to `pgid`. `SIGTTOU` is blocked meanwhile
+/// as the caller may not be in the foreground group yet.
+pub fn give_terminal(pgid: i32) {
+    unsafe {
+        let mut ttou: libc::sigset_t = std::mem::zeroed();
+        let mut previous: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut ttou);
+        libc::sigaddset(&mut ttou, libc::SIGTTOU);
+        libc::sigprocmask(libc::SIG_BLOCK, &ttou, &mut previous);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::sigprocmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+    }
+}
+
+/// Moves the calling process into `group`. Only async-signal-safe calls, to be
+/// usable right after `fork`.
+pub fn join_group(group: ProcessGroup) {
+    unsafe {
+        libc::setpgid(0, group.pgid);
+        if group.foreground {
+            give_terminal(libc::getpgrp());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    Stopped,
+    Done(i32),
+}
+
+#[derive(Debug)]
+pub struct Process {
+    /// `None` when the process could not be started at all.
+    pub pid: Option<Pid>,
+    pub state: ProcessState,
+}
+
+#[derive(Debug)]
+pub struct Job {
+    /// 0 until the job is inserted in a `JobTable`.
+    pub id: usize,
+    pub pgid: Pid,
+    pub processes: Vec<Process>,
+    pub command: String,
+}
+
+impl Job {
+    pub fn new(pgid: Pid, processes: Vec<Process>, command: String) -> Self {
+        Self {
+            id: 0,
+            pgid,
+            processes,
+            command,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.processes
+            .iter()
+            .all(|p| matches!(p.state, ProcessState::Done(_)))
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        !self.is_done()
+            && self
+                .processes
+                .iter()
+                .all(|p| p.state != ProcessState::Running)
+    }
+
+    /// Status of the last process, which is the status of the whole job.
+    pub fn status(&self) -> i32 {
+        match self.processes.last().map(|p| p.state) {
+            Some(ProcessState::Done(status)) => status,
+            _ => 0,
+        }
+    }
+
+    /// Sends `SIGCONT` to the job, to its whole process group when it has
+    /// one of its own.
+    pub fn resume(&mut self, own_group: bool) {
+        if own_group {
+            let _ = killpg(self.pgid, Signal::SIGCONT);
+        }
+        for process in &mut self.processes {
+            if let Some(pid) = process.pid {
+                if !own_group && process.state == ProcessState::Stopped {
+                    let _ = kill(pid, Signal::SIGCONT);
+                }
+            }
+            if process.state == ProcessState::Stopped {
+                process.state = ProcessState::Running;
+            }
+        }
+    }
+
+    fn update(&mut self, status: WaitStatus) {
+        let (pid, state) = match status {
+            WaitStatus::Exited(pid, code) => (pid, ProcessState::Done(code)),
+            WaitStatus::Signaled(pid, sig, _) => (pid, ProcessState::Done(128 + sig as i32)),
+            WaitStatus::Stopped(pid, _) => (pid, ProcessState::Stopped),
+            WaitStatus::Continued(pid) => (pid, ProcessState::Running),
+            _ => return,
+        };
+
+        if let Some(process) = self.processes.iter_mut().find(|p| p.pid == Some(pid)) {
+            process.state = state;
+        }
+    }
+
+    /// Blocks until every process of the job has either finished or stopped.
+    pub fn wait(&mut self) {
+        for i in 0..self.processes.len() {
+            while let Process {
+                pid: Some(pid),
+                state: ProcessState::Running,
+            } = self.processes[i]
+            {
+                match waitpid(pid, Some(WaitPidFlag::WUNTRACED)) {
+                    Ok(status) => self.update(status),
+                    Err(Errno::EINTR) => continue,
+                    Err(_) => self.processes[i].state = ProcessState::Done(1),
+                }
+            }
+        }
+    }
+
+    /// Collects state changes without blocking.
+    pub fn poll(&mut self) {
+        for i in 0..self.processes.len() {
+            let Process {
+                pid: Some(pid),
+                state: ProcessState::Running | ProcessState::Stopped,
+            } = self.processes[i]
+            else {
+                continue;
+            };
+
+            let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+            match waitpid(pid, Some(flags)) {
+                Ok(status) => self.update(status),
+                Err(Errno::EINTR) => {}
+                Err(_) => self.processes[i].state = ProcessState::Done(1),
+            }
+        }
+    }
+
+    pub fn state_label(&self) -> String {
+        if self.is_done() {
+            match self.status() {
+                0 => "Done".into(),
+                status => format!("Exit {status}"),
+            }
+        } else if self.is_stopped() {
+            "Stopped".into()
+        } else {
+            "Running".into()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    /// Job ids from least to most recently used; the last one is the current
+    /// job (`%+`) and the one before it the previous job (`%-`).
+    recency: Vec<usize>,
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Stores `job`, giving it the next free id unless it already has one,
+    /// and makes it the current job.
+    pub fn insert(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        let index = self.jobs.partition_point(|j| j.id < id);
+        self.jobs.insert(index, job);
+        self.touch(id);
+
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|j| j.id == id)?;
+        self.recency.retain(|i| *i != id);
+        Some(self.jobs.remove(index))
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|j| j.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|j| j.id).collect()
+    }
+
+    pub fn touch(&mut self, id: usize) {
+        self.recency.retain(|i| *i != id);
+        self.recency.push(id);
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.recency.last().copied()
+    }
+
+    pub fn previous(&self) -> Option<usize> {
+        self.recency.iter().rev().nth(1).copied()
+    }
+
+    /// `+` for the current job, `-` for the previous one.
+    pub fn mark(&self, id: usize) -> char {
+        if self.current() == Some(id) {
+            '+'
+        } else if self.previous() == Some(id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    /// Resolves a job spec: `%%`, `%+`, `%-`, `%N`, `N`, `%?text` or `%prefix`.
+    pub fn resolve(&self, spec: &str) -> Option<usize> {
+        let spec = spec.strip_prefix('%').unwrap_or(spec);
+        match spec {
+            "" | "%" | "+" => self.current(),
+            "-" => self.previous(),
+            _ => {
+                if let Ok(id) = spec.parse::<usize>() {
+                    return self.get(id).map(|j| j.id);
+                }
+                let matches = |job: &&Job| match spec.strip_prefix('?') {
+                    Some(text) => job.command.contains(text),
+                    None => job.command.starts_with(spec),
+                };
+                self.jobs.iter().rev().find(matches).map(|j| j.id)
+            }
+        }
+    }
+
+    pub fn poll(&mut self) {
+        for job in &mut self.jobs {
+            job.poll();
+        }
+    }
+
+    /// Polls every job and removes the finished ones, returning their
+    /// `jobs`-style report lines.
+    pub fn reap(&mut self) -> Vec<String> {
+        self.poll();
+
+        let done: Vec<usize> = self
+            .jobs
+            .iter()
+            .filter(|j| j.is_done())
+            .map(|j| j.id)
+            .collect();
+        let lines = done
+            .iter()
+            .filter_map(|id| self.get(*id))
+            .map(|job| self.format(job))
+            .collect();
+        for id in done {
+            self.remove(id);
+        }
+
+        lines
+    }
+
+    pub fn format(&self, job: &Job) -> String {
+        format!(
+            "[{}]{}  {:<24}{}",
+            job.id,
+            self.mark(job.id),
+            job.state_label(),
+            job.command
+        )
+    }
+}
+
+#[cfg(test)]
+fn job(command: &str) -> Job {
+    Job::new(Pid::from_raw(0), vec![], command.into())
+}
+
+#[test]
+fn test_job_table_resolve() {
+    let mut table = JobTable::new();
+    let sleep = table.insert(job("sleep 100"));
+    let vim = table.insert(job("vim notes.txt"));
+
+    assert_eq!((sleep, vim), (1, 2));
+    assert_eq!(table.resolve("%%"), Some(vim));
+    assert_eq!(table.resolve("%-"), Some(sleep));
+    assert_eq!(table.resolve("%1"), Some(sleep));
+    assert_eq!(table.resolve("2"), Some(vim));
+    assert_eq!(table.resolve("%sle"), Some(sleep));
+    assert_eq!(table.resolve("%?notes"), Some(vim));
+    assert_eq!(table.resolve("%3"), None);
+
+    table.touch(sleep);
+    assert_eq!(table.mark(sleep), '+');
+    assert_eq!(table.mark(vim), '-');
+
+    table.remove(sleep);
+    assert_eq!(table.insert(job("make")), 3);
+}
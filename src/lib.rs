@@ -1,3 +1,10 @@
+pub mod arith;
 pub mod cmd;
 pub mod error;
+pub mod exec;
+pub mod expand;
+pub mod glob;
+pub mod history;
 pub mod internals;
+pub mod jobs;
+pub mod vars;
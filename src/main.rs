@@ -1,19 +1,16 @@
 use dsh::{
-    // cmd,
-    // error::CommandError,
-    internals::{
-        // self,
-        get_internal_functions_map,
-    },
+    cmd::CmdParser,
+    exec::{Executor, Jump},
+    history::History,
 };
 use nix::sys::signal::{self, SigHandler, Signal};
-use std::ops::{Index, IndexMut};
+use std::ops::{Index, IndexMut, Range};
 use std::{
     env,
-    io::{self, prelude::*},
+    io::{self, prelude::*, IsTerminal},
     os::fd::AsRawFd,
+    path::PathBuf,
     sync::atomic::{AtomicBool, AtomicI32, Ordering},
-    usize,
 };
 use termion::{
     event::{
@@ -24,15 +21,66 @@ use termion::{
     input::TermRead,
     raw::IntoRawMode,
 };
-use tinytoken::Tokenizer;
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 static NEED_STOP: AtomicBool = AtomicBool::new(false);
 static STDIN_FD: AtomicI32 = AtomicI32::new(0);
 static CAN_STOP: AtomicBool = AtomicBool::new(true);
 
+const CONTINUATION_PROMPT: &str = "> ";
+const KILL_RING_SIZE: usize = 16;
+
 struct Shell {
-    internals: Option<dsh::internals::InternalFuncMap>,
+    executor: Executor,
+}
+
+/// An incremental history search, started with Ctrl-R or Ctrl-S. The line
+/// being edited shows the entry found as the query is typed.
+struct Search {
+    query: String,
+    forward: bool,
+    /// The entry matching `query`, `None` until something matched.
+    found: Option<usize>,
+    failing: bool,
+    /// The line from before the search, put back when it is aborted.
+    original: String,
+}
+
+impl Search {
+    fn new(forward: bool, original: String) -> Self {
+        Self {
+            query: String::new(),
+            forward,
+            found: None,
+            failing: false,
+            original,
+        }
+    }
+
+    fn prompt(&self) -> String {
+        format!(
+            "({}{}i-search)`{}': ",
+            if self.failing { "failing " } else { "" },
+            if self.forward { "" } else { "reverse-" },
+            self.query
+        )
+    }
+
+    /// Looks for the query from the entry found, or past it with `skip`,
+    /// towards the older or the newer entries.
+    fn search(&mut self, history: &History, skip: bool) {
+        let entries = history.entries();
+        let current = self.found.unwrap_or(entries.len());
+        let skip = skip || self.found.is_none();
+        let matches = |i: &usize| entries[*i].line.contains(&self.query);
+        let found = if self.forward {
+            (current + skip as usize..entries.len()).find(matches)
+        } else {
+            (0..current + !skip as usize).rev().find(matches)
+        };
+        self.failing = found.is_none();
+        self.found = found.or(self.found);
+    }
 }
 
 extern "C" fn handle_sighup(signal: libc::c_int) {
@@ -49,20 +97,8 @@ extern "C" fn handle_sigint(_signal: libc::c_int) {
     // std::process::exit(0);
 }
 
-struct KeyModifiers {
-    alt: bool,
-    ctrl: bool,
-    shift: bool,
-}
-
-impl KeyModifiers {
-    pub fn new() -> Self {
-        Self {
-            alt: false,
-            ctrl: false,
-            shift: false,
-        }
-    }
+extern "C" fn handle_sigtstp(_signal: libc::c_int) {
+    // The shell itself never stops, Ctrl-Z is for the foreground job.
 }
 
 struct TextBuffer {
@@ -79,7 +115,7 @@ impl Index<usize> for TextBuffer {
 }
 
 impl IndexMut<usize> for TextBuffer {
-    fn index_mut<'a>(&'a mut self, i: usize) -> &'a mut char {
+    fn index_mut(&mut self, i: usize) -> &mut char {
         &mut self._buf[i]
     }
 }
@@ -110,45 +146,154 @@ impl TextBuffer {
     }
 
     pub fn len(&self) -> usize {
-        return self._buf.len();
+        self._buf.len()
     }
 
     pub fn width(&self) -> usize {
+        self.width_to(self._buf.len())
+    }
+
+    /// Width of the characters before `index`, where the cursor goes.
+    pub fn width_to(&self, index: usize) -> usize {
         let mut w = 0usize;
-        for ch in &self._buf {
+        for ch in &self._buf[..index] {
             w += ch.width().unwrap_or(0);
         }
         w
     }
 
+    /// Inserts `text` at `index` and returns how many characters it has.
+    pub fn insert_str(&mut self, index: usize, text: &str) -> usize {
+        let count = text.chars().count();
+        self._buf.splice(index..index, text.chars());
+        count
+    }
+
+    pub fn drain(&mut self, range: Range<usize>) -> String {
+        self._buf.drain(range).collect()
+    }
+
+    /// Start of the word before `index`, words being made of the characters
+    /// `is_word` accepts.
+    pub fn word_start(&self, index: usize, is_word: impl Fn(char) -> bool) -> usize {
+        let mut i = index;
+        while i > 0 && !is_word(self._buf[i - 1]) {
+            i -= 1;
+        }
+        while i > 0 && is_word(self._buf[i - 1]) {
+            i -= 1;
+        }
+        i
+    }
+
+    /// End of the word after `index`.
+    pub fn word_end(&self, index: usize, is_word: impl Fn(char) -> bool) -> usize {
+        let mut i = index;
+        while i < self._buf.len() && !is_word(self._buf[i]) {
+            i += 1;
+        }
+        while i < self._buf.len() && is_word(self._buf[i]) {
+            i += 1;
+        }
+        i
+    }
+
     pub fn clear(&mut self) {
         self.reset();
     }
 
+    /// Replaces the contents with `text`.
+    pub fn set(&mut self, text: &str) {
+        self.reset();
+        self._buf.extend(text.chars());
+    }
+
     fn reset(&mut self) {
         self._capacity = 2048;
         self._buf = Vec::with_capacity(self._capacity);
     }
 }
 
-impl ToString for TextBuffer {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for TextBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut str_dupa = String::new();
         for ch in &self._buf {
             str_dupa.push(*ch);
         }
-        str_dupa
+        write!(f, "{str_dupa}")
+    }
+}
+
+/// Text removed by the kill commands, for Ctrl-Y to put back and Alt-Y to
+/// cycle through.
+struct KillRing {
+    kills: Vec<String>,
+    /// The kill put back by the last key if it was Ctrl-Y or Alt-Y, and where
+    /// it is in the line.
+    yank: Option<(usize, Range<usize>)>,
+}
+
+impl KillRing {
+    fn new() -> Self {
+        Self {
+            kills: vec![],
+            yank: None,
+        }
+    }
+
+    /// Adds `text`, or with `merge` adds it to the last kill, in front of it
+    /// for text that was before the cursor.
+    fn kill(&mut self, text: String, merge: bool, backward: bool) {
+        match self.kills.last_mut() {
+            Some(last) if merge => match backward {
+                true => last.insert_str(0, &text),
+                false => last.push_str(&text),
+            },
+            _ if text.is_empty() => {}
+            _ => {
+                self.kills.push(text);
+                if self.kills.len() > KILL_RING_SIZE {
+                    self.kills.remove(0);
+                }
+            }
+        }
+    }
+
+    /// Inserts kill `index` at `at` and returns the position after it.
+    fn yank(&mut self, index: usize, cmd_buff: &mut TextBuffer, at: usize) -> usize {
+        let end = at + cmd_buff.insert_str(at, &self.kills[index]);
+        self.yank = Some((index, at..end));
+        end
+    }
+
+    /// Replaces the kill `last` yanked with the one before it and returns the
+    /// position after it.
+    fn yank_pop(&mut self, last: (usize, Range<usize>), cmd_buff: &mut TextBuffer) -> usize {
+        let (index, range) = last;
+        let count = self.kills.len();
+        let at = range.start;
+        cmd_buff.drain(range);
+        self.yank((index + count - 1) % count, cmd_buff, at)
     }
 }
 
 impl Shell {
-    const fn new() -> Self {
-        Self { internals: None }
+    fn new() -> Self {
+        Self {
+            executor: Executor::new(),
+        }
     }
 
-    fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.internals = Some(get_internal_functions_map());
+    fn prompt(&self) -> String {
+        format!(
+            "{}@{} [{}] ",
+            env::var("USERNAME").unwrap_or("".to_string()),
+            env::var("hostname").unwrap_or("".to_string()),
+            self.executor.last_status,
+        )
+    }
 
+    fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         let stdin = io::stdin();
         STDIN_FD.store(stdin.as_raw_fd(), Ordering::Relaxed);
         let mut stdout = io::stdout().into_raw_mode()?;
@@ -163,15 +308,7 @@ impl Shell {
             }
         }
 
-        let error_code = 0;
-        let mut modifiers = KeyModifiers::new();
-
-        let mut prompt = format!(
-            "{}@{} [{}] ",
-            env::var("USERNAME").unwrap_or("".to_string()),
-            env::var("hostname").unwrap_or("".to_string()),
-            error_code,
-        );
+        let mut prompt = self.prompt();
 
         write!(
             stdout,
@@ -182,110 +319,278 @@ impl Shell {
         .unwrap();
         stdout.flush()?;
         let mut cmd_buff = TextBuffer::new();
+        // Lines already entered for a command that is not complete yet.
+        let mut pending = String::new();
         let mut insert_position = 0u16;
         let mut cursor_position = 0u16;
+        // The history entry shown while going through it with Up and Down,
+        // and the line that was being typed before.
+        let mut history_index: Option<usize> = None;
+        let mut typed_line = String::new();
+        let mut search: Option<Search> = None;
+        let mut kill_ring = KillRing::new();
+        // Whether the last key killed text, so that the next kill adds to it.
+        let mut killed = false;
 
         for c in stdin.events() {
             let ev = c.unwrap();
-            match ev {
-                Event::Key(key) => match key {
-                    Key::Backspace => {
-                        if insert_position > 0 {
-                            let c = cmd_buff.remove((insert_position - 1) as usize);
-                            insert_position -= 1;
-                            cursor_position -= c.width().unwrap_or(0) as u16;
-
-                            write!(stdout, "\r{}{}", termion::clear::CurrentLine, prompt)?;
-                            write!(stdout, "{}", cmd_buff.to_string())?;
-
-                            let move_left = cmd_buff.width() - cursor_position as usize;
-                            if move_left > 0 {
-                                write!(stdout, "{}", termion::cursor::Left(move_left as u16))?;
+            let merge_kill = std::mem::take(&mut killed);
+            let last_yank = kill_ring.yank.take();
+            if let (Some(state), Event::Key(key)) = (&mut search, &ev) {
+                let history = &self.executor.history;
+                match key {
+                    Key::Char('\n')
+                    | Key::Ctrl('c')
+                    | Key::Esc
+                    | Key::Left
+                    | Key::Right
+                    | Key::Up
+                    | Key::Down => {
+                        // The line found is kept for editing, Enter and
+                        // Ctrl-C then go on as usual.
+                        if state.found.is_some() {
+                            if history_index.is_none() {
+                                typed_line = std::mem::take(&mut state.original);
                             }
+                            history_index = state.found;
+                        }
+                        let line = cmd_buff.to_string();
+                        let start = line.find(&state.query).unwrap_or(line.len());
+                        insert_position = line[..start].chars().count() as u16;
+                        cursor_position = line[..start].width() as u16;
+                        search = None;
+                        if !matches!(key, Key::Char('\n') | Key::Ctrl('c')) {
+                            redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
+                            stdout.flush()?;
+                            continue;
                         }
                     }
-                    Key::Left => {
-                        if insert_position > 0 {
-                            let back_by = cmd_buff[insert_position as usize - 1usize]
-                                .width()
-                                .unwrap_or(0) as u16;
-                            cursor_position -= back_by;
-                            insert_position -= 1;
-                            let _ = write!(stdout, "{}", termion::cursor::Left(back_by));
+                    Key::Ctrl('g') => {
+                        cmd_buff.set(&state.original);
+                        insert_position = cmd_buff.len() as u16;
+                        cursor_position = cmd_buff.width() as u16;
+                        search = None;
+                        redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
+                        stdout.flush()?;
+                        continue;
+                    }
+                    _ => {
+                        match key {
+                            Key::Ctrl('r') | Key::Ctrl('s') => {
+                                state.forward = *key == Key::Ctrl('s');
+                                state.search(history, true);
+                            }
+                            Key::Backspace => {
+                                state.query.pop();
+                                state.search(history, false);
+                            }
+                            Key::Char(ch) if !ch.is_control() => {
+                                state.query.push(*ch);
+                                state.search(history, false);
+                            }
+                            _ => {}
                         }
+                        if let Some(entry) = state.found.and_then(|i| history.get(i)) {
+                            cmd_buff.set(&entry.line);
+                        }
+                        let width = cmd_buff.width() as u16;
+                        redraw(&mut stdout, &state.prompt(), &cmd_buff, width)?;
+                        stdout.flush()?;
+                        continue;
+                    }
+                }
+            }
+            match ev {
+                Event::Key(key) => match key {
+                    Key::Backspace if insert_position > 0 => {
+                        let c = cmd_buff.remove((insert_position - 1) as usize);
+                        insert_position -= 1;
+                        cursor_position -= c.width().unwrap_or(0) as u16;
+                        redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
+                    }
+                    Key::Left | Key::Ctrl('b') if insert_position > 0 => {
+                        let back_by = cmd_buff[insert_position as usize - 1usize]
+                            .width()
+                            .unwrap_or(0) as u16;
+                        cursor_position -= back_by;
+                        insert_position -= 1;
+                        let _ = write!(stdout, "{}", termion::cursor::Left(back_by));
                     }
                     // Key::ShiftLeft => todo!(),
                     // Key::AltLeft => todo!(),
-                    // Key::CtrlLeft => todo!(),
-                    Key::Right => {
-                        if insert_position < cmd_buff.len() as u16 {
-                            let adv_by =
-                                cmd_buff[insert_position as usize].width().unwrap_or(0) as u16;
-                            cursor_position += adv_by;
-                            insert_position += 1;
-                            let _ = write!(stdout, "{}", termion::cursor::Right(adv_by));
-                        }
+                    Key::Right | Key::Ctrl('f') if insert_position < cmd_buff.len() as u16 => {
+                        let adv_by = cmd_buff[insert_position as usize].width().unwrap_or(0) as u16;
+                        cursor_position += adv_by;
+                        insert_position += 1;
+                        let _ = write!(stdout, "{}", termion::cursor::Right(adv_by));
                     }
                     // Key::ShiftRight => todo!(),
                     // Key::AltRight => todo!(),
-                    // Key::CtrlRight => todo!(),
-                    // Key::Up => todo!(),
-                    Key::ShiftUp => {
-                        modifiers.shift = false;
+                    Key::Ctrl('a')
+                    | Key::Home
+                    | Key::Ctrl('e')
+                    | Key::End
+                    | Key::Alt('b')
+                    | Key::CtrlLeft
+                    | Key::Alt('f')
+                    | Key::CtrlRight => {
+                        let at = insert_position as usize;
+                        insert_position = match key {
+                            Key::Ctrl('a') | Key::Home => 0,
+                            Key::Ctrl('e') | Key::End => cmd_buff.len(),
+                            Key::Alt('b') | Key::CtrlLeft => {
+                                cmd_buff.word_start(at, char::is_alphanumeric)
+                            }
+                            _ => cmd_buff.word_end(at, char::is_alphanumeric),
+                        } as u16;
+                        cursor_position = cmd_buff.width_to(insert_position as usize) as u16;
+                        redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
+                    }
+                    // Ctrl-W kills a blank separated word, Alt-D an
+                    // alphanumeric one.
+                    Key::Ctrl('w') | Key::Alt('d') | Key::Ctrl('k') | Key::Ctrl('u') => {
+                        let at = insert_position as usize;
+                        let range = match key {
+                            Key::Ctrl('w') => cmd_buff.word_start(at, |c| !c.is_whitespace())..at,
+                            Key::Alt('d') => at..cmd_buff.word_end(at, char::is_alphanumeric),
+                            Key::Ctrl('k') => at..cmd_buff.len(),
+                            _ => 0..at,
+                        };
+                        let backward = range.end == at;
+                        insert_position = range.start as u16;
+                        kill_ring.kill(cmd_buff.drain(range), merge_kill, backward);
+                        killed = true;
+                        cursor_position = cmd_buff.width_to(insert_position as usize) as u16;
+                        redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
                     }
-                    Key::AltUp => {
-                        modifiers.alt = false;
+                    Key::Ctrl('y') if !kill_ring.kills.is_empty() => {
+                        let last = kill_ring.kills.len() - 1;
+                        let at = insert_position as usize;
+                        insert_position = kill_ring.yank(last, &mut cmd_buff, at) as u16;
+                        cursor_position = cmd_buff.width_to(insert_position as usize) as u16;
+                        redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
+                    }
+                    // Replaces what was just yanked with the kill before it.
+                    Key::Alt('y') => {
+                        if let Some(last) = last_yank {
+                            insert_position = kill_ring.yank_pop(last, &mut cmd_buff) as u16;
+                            cursor_position = cmd_buff.width_to(insert_position as usize) as u16;
+                            redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
+                        }
                     }
-                    Key::CtrlUp => {
-                        modifiers.ctrl = false;
+                    // Swaps the characters around the cursor, or the last two
+                    // at the end of the line.
+                    Key::Ctrl('t') if insert_position > 0 && cmd_buff.len() > 1 => {
+                        let at = (insert_position as usize).min(cmd_buff.len() - 1);
+                        let ch = cmd_buff.remove(at - 1);
+                        cmd_buff.insert(at, ch);
+                        insert_position = at as u16 + 1;
+                        cursor_position = cmd_buff.width_to(insert_position as usize) as u16;
+                        redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
                     }
-                    // Key::Down => todo!(),
-                    Key::ShiftDown => {
-                        modifiers.shift = true;
+                    Key::Ctrl('l') => {
+                        write!(
+                            stdout,
+                            "{}{}",
+                            termion::clear::All,
+                            termion::cursor::Goto(1, 1)
+                        )?;
+                        redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
                     }
-                    Key::AltDown => {
-                        modifiers.alt = true;
+                    Key::Delete | Key::Ctrl('d') if (insert_position as usize) < cmd_buff.len() => {
+                        cmd_buff.remove(insert_position as usize);
+                        redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
                     }
-                    Key::CtrlDown => {
-                        modifiers.ctrl = true;
+                    Key::Up | Key::Down => {
+                        let history = &self.executor.history;
+                        let index = match (key, history_index) {
+                            (Key::Up, None) if !history.is_empty() => history.len() - 1,
+                            (Key::Up, Some(i)) if i > 0 => i - 1,
+                            (Key::Down, Some(i)) => i + 1,
+                            _ => continue,
+                        };
+                        if history_index.is_none() {
+                            typed_line = cmd_buff.to_string();
+                        }
+                        match history.get(index) {
+                            Some(entry) => {
+                                history_index = Some(index);
+                                cmd_buff.set(&entry.line);
+                            }
+                            None => {
+                                history_index = None;
+                                cmd_buff.set(&std::mem::take(&mut typed_line));
+                            }
+                        }
+                        insert_position = cmd_buff.len() as u16;
+                        cursor_position = cmd_buff.width() as u16;
+                        redraw(&mut stdout, &prompt, &cmd_buff, cursor_position)?;
                     }
-                    // Key::Home => {}
                     // Key::CtrlHome => todo!(),
-                    // Key::End => {}
                     // Key::CtrlEnd => todo!(),
                     // Key::PageUp => todo!(),
                     // Key::PageDown => todo!(),
                     // Key::BackTab => todo!(),
-                    // Key::Delete => todo!(),
                     // Key::Insert => todo!(),
                     // Key::F(_) => todo!(),
                     Key::Char(ch) => {
                         if ch == '\n' {
-                            let _ = write!(stdout, "\n");
-                            let _tokens = Tokenizer::builder()
-                                .ignore_numbers(true)
-                                .parse_char_as_string(true)
-                                .add_symbol('=')
-                                .build(&cmd_buff.to_string())
-                                .tokenize()?;
+                            let _ = write!(stdout, "\r\n");
+                            let mut line = std::mem::take(&mut pending);
+                            let typed = cmd_buff.to_string();
                             cmd_buff.clear();
-                            stdout.flush()?;
-                            let display_length = prompt.width() as u16 + cursor_position;
                             insert_position = 0;
                             cursor_position = 0;
-                            let _ = write!(
-                                stdout,
-                                "{}{}",
-                                termion::clear::CurrentLine,
-                                termion::cursor::Left(display_length)
-                            );
+                            history_index = None;
+
+                            match self.executor.history.expand(&typed) {
+                                Ok(Some(expanded)) => {
+                                    write!(stdout, "{}\r\n", expanded.replace('\n', "\r\n"))?;
+                                    line.push_str(&expanded);
+                                }
+                                Ok(None) => line.push_str(&typed),
+                                Err(e) => {
+                                    eprint!("{e}\r\n");
+                                    self.executor.last_status = e.status();
+                                    prompt = self.prompt();
+                                    write!(stdout, "{}{}", termion::clear::CurrentLine, prompt)?;
+                                    stdout.flush()?;
+                                    continue;
+                                }
+                            }
                             stdout.flush()?;
-                            prompt = format!(
-                                "{}@{} [{}] ",
-                                env::var("USERNAME").unwrap_or("".to_string()),
-                                env::var("hostname").unwrap_or("".to_string()),
-                                error_code,
-                            );
+
+                            match CmdParser::parse(&line) {
+                                Err(e) if e.is_incomplete() => {
+                                    pending = line;
+                                    pending.push('\n');
+                                    prompt = CONTINUATION_PROMPT.into();
+                                }
+                                parsed => {
+                                    stdout.suspend_raw_mode()?;
+                                    let history = &mut self.executor.history;
+                                    history.configure(&self.executor.vars);
+                                    if let Err(e) = history.add(&line) {
+                                        eprintln!("[dsh]: history: {e}");
+                                    }
+                                    match parsed {
+                                        Ok(list) => {
+                                            self.executor.execute(&list);
+                                            if self.executor.jump == Some(Jump::Exit) {
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            eprintln!("{e}");
+                                            self.executor.last_status = 2;
+                                        }
+                                    }
+                                    self.executor.notify_jobs();
+                                    stdout.activate_raw_mode()?;
+                                    prompt = self.prompt();
+                                }
+                            }
+
                             write!(stdout, "{}{}", termion::clear::CurrentLine, prompt)?;
                             // let _ = write!(
                             //     stdout,
@@ -307,7 +612,7 @@ impl Shell {
                             cmd_buff.insert(insert_position as usize, ch);
                             insert_position += 1;
 
-                            let _ = write!(stdout, "{}", cmd_buff.to_string());
+                            let _ = write!(stdout, "{}", cmd_buff);
                             stdout.flush()?;
                             let move_left = cmd_buff.width() - cursor_position as usize;
                             if move_left > 0 {
@@ -317,10 +622,24 @@ impl Shell {
                         }
                     }
                     // Key::Alt(_) => todo!(),
-                    Key::Ctrl(c) => {
-                        if c.to_lowercase().to_string() == "d" {
-                            break;
-                        }
+                    Key::Ctrl('r') | Key::Ctrl('s') => {
+                        let state = Search::new(key == Key::Ctrl('s'), cmd_buff.to_string());
+                        let width = cmd_buff.width() as u16;
+                        redraw(&mut stdout, &state.prompt(), &cmd_buff, width)?;
+                        search = Some(state);
+                    }
+                    // End of input only on an empty line.
+                    Key::Ctrl(c) if c.to_lowercase().to_string() == "d" && cmd_buff.len() == 0 => {
+                        break;
+                    }
+                    Key::Ctrl('c') => {
+                        pending.clear();
+                        cmd_buff.clear();
+                        insert_position = 0;
+                        cursor_position = 0;
+                        history_index = None;
+                        prompt = self.prompt();
+                        write!(stdout, "^C\r\n{}{}", termion::clear::CurrentLine, prompt)?;
                     }
                     Key::Null => {
                         break;
@@ -337,13 +656,265 @@ impl Shell {
     }
 }
 
+/// Rewrites the prompt and the line, then puts the cursor back at
+/// `cursor_position` columns into the line.
+fn redraw(
+    stdout: &mut impl Write,
+    prompt: &str,
+    cmd_buff: &TextBuffer,
+    cursor_position: u16,
+) -> io::Result<()> {
+    write!(
+        stdout,
+        "\r{}{prompt}{cmd_buff}",
+        termion::clear::CurrentLine
+    )?;
+    let move_left = cmd_buff.width() - cursor_position as usize;
+    if move_left > 0 {
+        write!(stdout, "{}", termion::cursor::Left(move_left as u16))?;
+    }
+    Ok(())
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("[dsh]: {message}");
+    eprintln!("usage: dsh [-l] [-c command [name [arg...]] | script [arg...]]");
+    std::process::exit(2)
+}
+
+/// `~/.dsh_profile` for login shells, then `~/.dshrc` and
+/// `$XDG_CONFIG_HOME/dsh/config` for interactive ones.
+fn startup_files(executor: &Executor, login: bool, interactive: bool) -> Vec<PathBuf> {
+    let Some(home) = executor.vars.get("HOME").map(PathBuf::from) else {
+        return vec![];
+    };
+    let mut files = vec![];
+    if login {
+        files.push(home.join(".dsh_profile"));
+    }
+    if interactive {
+        let config = match executor.vars.get("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => home.join(".config"),
+        };
+        files.push(home.join(".dshrc"));
+        files.push(config.join("dsh").join("config"));
+    }
+    files
+}
+
+/// Where the commands of a non-interactive shell come from.
+enum Source {
+    Text(String),
+    /// Read as the commands run, so that they can read the rest of stdin.
+    Stdin,
+}
+
+/// Reads the next line of stdin with its newline, a byte at a time so that
+/// nothing past it is taken from the commands run.
+fn stdin_line() -> Option<String> {
+    let mut line = vec![];
+    let mut byte = [0u8];
+    loop {
+        match nix::unistd::read(libc::STDIN_FILENO, &mut byte) {
+            Ok(0) => break,
+            Ok(_) => {
+                line.push(byte[0]);
+                if byte[0] == b'\n' {
+                    break;
+                }
+            }
+            Err(nix::errno::Errno::EINTR) => {}
+            Err(e) => {
+                eprintln!("[dsh]: stdin: {e}");
+                break;
+            }
+        }
+    }
+    (!line.is_empty()).then(|| String::from_utf8_lossy(&line).into_owned())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let hup_handler = SigHandler::Handler(handle_sighup);
-    let int_handler = SigHandler::Handler(handle_sigint);
-    unsafe { signal::signal(Signal::SIGHUP, hup_handler) }.unwrap();
-    unsafe { signal::signal(Signal::SIGINT, int_handler) }.unwrap();
+    let mut args = env::args();
+    // `login` runs a login shell as `-dsh`.
+    let mut login = args.next().is_some_and(|arg0| arg0.starts_with('-'));
+    let mut args: Vec<String> = args.collect();
+    while matches!(args.first().map(String::as_str), Some("-l" | "--login")) {
+        args.remove(0);
+        login = true;
+    }
     let mut shell = Shell::new();
-    shell.run()?;
+    let executor = &mut shell.executor;
+
+    // `-c command [name [arg...]]`, `script [arg...]`, a script piped on
+    // stdin, or else an interactive session.
+    let source = match args.first().map(String::as_str) {
+        Some("-c") => {
+            let Some(command) = args.get(1) else {
+                usage_error("-c: option requires an argument");
+            };
+            if let Some(name) = args.get(2) {
+                executor.arg0 = name.clone();
+            }
+            executor.positional = args.iter().skip(3).cloned().collect();
+            Some(Source::Text(command.clone()))
+        }
+        Some(option) if option.starts_with('-') => {
+            usage_error(&format!("{option}: invalid option"))
+        }
+        Some(path) => {
+            let source = std::fs::read_to_string(path).unwrap_or_else(|e| {
+                eprintln!("[dsh]: {path}: {e}");
+                std::process::exit(127)
+            });
+            executor.arg0 = path.to_string();
+            executor.positional = args[1..].to_vec();
+            Some(Source::Text(source))
+        }
+        None if !io::stdin().is_terminal() => Some(Source::Stdin),
+        None => None,
+    };
+
+    // The startup files already run with the signals and the terminal set
+    // up as for the session.
+    if source.is_none() {
+        let hup_handler = SigHandler::Handler(handle_sighup);
+        let int_handler = SigHandler::Handler(handle_sigint);
+        let tstp_handler = SigHandler::Handler(handle_sigtstp);
+        unsafe { signal::signal(Signal::SIGHUP, hup_handler) }.unwrap();
+        unsafe { signal::signal(Signal::SIGINT, int_handler) }.unwrap();
+        unsafe { signal::signal(Signal::SIGTSTP, tstp_handler) }.unwrap();
+        if io::stdin().is_terminal() {
+            executor.enable_job_control();
+        }
+    }
 
-    Ok(())
+    for path in startup_files(executor, login, source.is_none()) {
+        executor.run_startup_file(&path);
+        if executor.jump == Some(Jump::Exit) {
+            std::process::exit(executor.last_status);
+        }
+    }
+
+    let Some(source) = source else {
+        executor.history.configure(&executor.vars);
+        if let Err(e) = executor.history.load() {
+            eprintln!("[dsh]: history: {e}");
+        }
+        shell.run()?;
+        std::process::exit(shell.executor.last_status);
+    };
+
+    let status = match source {
+        Source::Text(source) => executor.run_script(&source),
+        Source::Stdin => executor.run_lines(std::iter::from_fn(stdin_line)),
+    };
+    let _ = io::stdout().flush();
+    std::process::exit(status)
+}
+
+#[cfg(test)]
+fn buffer(text: &str) -> TextBuffer {
+    let mut buffer = TextBuffer::new();
+    buffer.set(text);
+    buffer
+}
+
+#[test]
+fn test_word_boundaries() {
+    let line = buffer("  git commit -m 'fix: x.y'  ");
+    let blank = |c: char| !c.is_whitespace();
+    assert_eq!(line.word_start(line.len(), char::is_alphanumeric), 24);
+    assert_eq!(line.word_start(24, char::is_alphanumeric), 22);
+    assert_eq!(line.word_start(22, char::is_alphanumeric), 17);
+    assert_eq!(line.word_start(line.len(), blank), 22);
+    assert_eq!(line.word_start(16, blank), 13);
+    assert_eq!(line.word_start(2, blank), 0);
+    assert_eq!(line.word_start(0, blank), 0);
+    assert_eq!(line.word_end(0, char::is_alphanumeric), 5);
+    assert_eq!(line.word_end(15, char::is_alphanumeric), 20);
+    assert_eq!(line.word_end(20, char::is_alphanumeric), 23);
+    assert_eq!(line.word_end(25, char::is_alphanumeric), line.len());
+    assert_eq!(line.word_end(line.len(), char::is_alphanumeric), line.len());
+    assert_eq!(buffer("").word_start(0, blank), 0);
+    assert_eq!(buffer("").word_end(0, blank), 0);
+}
+
+#[test]
+fn test_kill_ring() {
+    let mut ring = KillRing::new();
+    // Consecutive kills make one, backward ones go in front.
+    ring.kill("two ".to_string(), false, true);
+    ring.kill("one ".to_string(), true, true);
+    ring.kill("three".to_string(), true, false);
+    assert_eq!(ring.kills, ["one two three"]);
+    ring.kill(String::new(), false, false);
+    assert_eq!(ring.kills.len(), 1);
+    ring.kill("a".to_string(), false, false);
+    ring.kill("b".to_string(), false, true);
+    assert_eq!(ring.kills, ["one two three", "a", "b"]);
+
+    // Yanking puts back the last kill, yank-pop then goes round the ring.
+    let mut line = buffer("[]");
+    assert_eq!(ring.yank(2, &mut line, 1), 2);
+    assert_eq!(line.to_string(), "[b]");
+    assert_eq!(ring.yank, Some((2, 1..2)));
+    let last = ring.yank.take().unwrap();
+    assert_eq!(ring.yank_pop(last, &mut line), 2);
+    assert_eq!(line.to_string(), "[a]");
+    let last = ring.yank.take().unwrap();
+    assert_eq!(ring.yank_pop(last, &mut line), 14);
+    assert_eq!(line.to_string(), "[one two three]");
+    let last = ring.yank.take().unwrap();
+    assert_eq!(ring.yank_pop(last, &mut line), 2);
+    assert_eq!(line.to_string(), "[b]");
+
+    for i in 0..KILL_RING_SIZE {
+        ring.kill(i.to_string(), false, false);
+    }
+    assert_eq!(ring.kills.len(), KILL_RING_SIZE);
+    assert_eq!(ring.kills[0], "0");
+}
+
+#[test]
+fn test_history_search() {
+    let mut history = History::new();
+    for line in ["ls /tmp", "echo a", "ls -l", "make"] {
+        history.add(line).unwrap();
+    }
+    let mut search = Search::new(false, "typed".to_string());
+    search.query.push_str("ls");
+    search.search(&history, false);
+    assert_eq!((search.found, search.failing), (Some(2), false));
+    // Typing more keeps the entry while it still matches.
+    search.query.push_str(" -");
+    search.search(&history, false);
+    assert_eq!((search.found, search.failing), (Some(2), false));
+    search.query.truncate(2);
+    search.search(&history, true);
+    assert_eq!((search.found, search.failing), (Some(0), false));
+    // Nothing older matches, the last entry found stays.
+    search.search(&history, true);
+    assert_eq!((search.found, search.failing), (Some(0), true));
+    assert_eq!(search.prompt(), "(failing reverse-i-search)`ls': ");
+
+    search.forward = true;
+    search.search(&history, true);
+    assert_eq!((search.found, search.failing), (Some(2), false));
+    assert_eq!(search.prompt(), "(i-search)`ls': ");
+    search.search(&history, true);
+    assert_eq!((search.found, search.failing), (Some(2), true));
+
+    search.query = "zzz".to_string();
+    search.search(&history, false);
+    assert_eq!((search.found, search.failing), (Some(2), true));
+
+    // There is nothing newer than the line being typed.
+    let mut search = Search::new(true, String::new());
+    search.query.push('m');
+    search.search(&history, false);
+    assert_eq!((search.found, search.failing), (None, true));
+    search.forward = false;
+    search.search(&history, true);
+    assert_eq!((search.found, search.failing), (Some(3), false));
 }
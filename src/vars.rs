@@ -0,0 +1,236 @@
+use std::{collections::HashMap, env};
+
+use crate::error::CommandError;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variable {
+    /// `None` for a variable that was declared (`export NAME`) but never set.
+    pub value: Option<String>,
+    /// Exported variables make up the environment of every child process.
+    pub exported: bool,
+    pub readonly: bool,
+}
+
+/// The shell's own variables. Children never see the shell process
+/// environment directly, only what is exported from here.
+#[derive(Debug, Default)]
+pub struct Variables {
+    vars: HashMap<String, Variable>,
+    /// For each function being run, the variables it made local and what
+    /// they were before, to be put back when it returns.
+    scopes: Vec<HashMap<String, Option<Variable>>>,
+}
+
+/// Whether `name` can be used as a variable name.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn readonly_error(name: &str) -> CommandError {
+    CommandError::Custom {
+        prog_name: name.into(),
+        message: "readonly variable".into(),
+        status: 1,
+    }
+}
+
+/// Quotes `value` so that it reads back as the same word inside `"..."`.
+fn double_quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Imports the environment the shell was started with, all exported.
+    pub fn from_env() -> Self {
+        let vars = env::vars()
+            .map(|(name, value)| {
+                (
+                    name,
+                    Variable {
+                        value: Some(value),
+                        exported: true,
+                        readonly: false,
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            vars,
+            scopes: vec![],
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name)?.value.as_deref()
+    }
+
+    /// Sets `name`, keeping it exported if it already was.
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), CommandError> {
+        let var = self.vars.entry(name.to_string()).or_default();
+        if var.readonly {
+            return Err(readonly_error(name));
+        }
+        var.value = Some(value);
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<(), CommandError> {
+        if self.vars.get(name).is_some_and(|var| var.readonly) {
+            return Err(readonly_error(name));
+        }
+        self.vars.remove(name);
+        Ok(())
+    }
+
+    pub fn set_exported(&mut self, name: &str, exported: bool) {
+        self.vars.entry(name.to_string()).or_default().exported = exported;
+    }
+
+    pub fn set_readonly(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default().readonly = true;
+    }
+
+    /// Starts the scope of a function call.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Ends the scope of a function call, restoring its local variables.
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for (name, var) in scope {
+            match var {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+    }
+
+    /// Makes `name` local to the innermost function call, unset until
+    /// assigned.
+    pub fn make_local(&mut self, name: &str) -> Result<(), CommandError> {
+        let Some(scope) = self.scopes.last_mut() else {
+            return Err(CommandError::Custom {
+                prog_name: "local".into(),
+                message: "can only be used in a function".into(),
+                status: 1,
+            });
+        };
+        if self.vars.get(name).is_some_and(|var| var.readonly) {
+            return Err(readonly_error(name));
+        }
+        if !scope.contains_key(name) {
+            scope.insert(name.to_string(), self.vars.remove(name));
+        }
+        Ok(())
+    }
+
+    /// Name/value pairs to pass as the environment of a child process.
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().filter_map(|(name, var)| match &var.value {
+            Some(value) if var.exported => Some((name.as_str(), value.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Every variable name, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.vars.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn attributes(&self, name: &str) -> Option<&Variable> {
+        self.vars.get(name)
+    }
+
+    /// `declare -p` line recreating `name`, e.g. `declare -rx PATH="/bin"`.
+    pub fn declaration(&self, name: &str) -> Option<String> {
+        let var = self.vars.get(name)?;
+        let mut flags = String::new();
+        if var.readonly {
+            flags.push('r');
+        }
+        if var.exported {
+            flags.push('x');
+        }
+        if flags.is_empty() {
+            flags.push('-');
+        }
+
+        Some(match &var.value {
+            Some(value) => format!("declare -{flags} {name}={}", double_quote(value)),
+            None => format!("declare -{flags} {name}"),
+        })
+    }
+}
+
+#[test]
+fn test_set_keeps_export() {
+    let mut vars = Variables::new();
+    vars.set("LOCAL", "1".into()).unwrap();
+    vars.set("SHARED", "a".into()).unwrap();
+    vars.set_exported("SHARED", true);
+    vars.set("SHARED", "b".into()).unwrap();
+    vars.set_exported("DECLARED", true);
+
+    assert_eq!(vars.get("LOCAL"), Some("1"));
+    assert_eq!(vars.exported().collect::<Vec<_>>(), vec![("SHARED", "b")]);
+    assert_eq!(vars.declaration("DECLARED").unwrap(), "declare -x DECLARED");
+}
+
+#[test]
+fn test_local_scopes() {
+    let mut vars = Variables::new();
+    vars.set("X", "global".into()).unwrap();
+    assert!(vars.make_local("X").is_err());
+
+    vars.push_scope();
+    vars.make_local("X").unwrap();
+    assert_eq!(vars.get("X"), None);
+    vars.set("X", "outer".into()).unwrap();
+    vars.push_scope();
+    vars.make_local("X").unwrap();
+    vars.make_local("Y").unwrap();
+    vars.set("X", "inner".into()).unwrap();
+    vars.set("Y", "inner".into()).unwrap();
+    vars.pop_scope();
+    assert_eq!(vars.get("X"), Some("outer"));
+    assert_eq!(vars.get("Y"), None);
+    vars.pop_scope();
+
+    assert_eq!(vars.get("X"), Some("global"));
+}
+
+#[test]
+fn test_readonly() {
+    let mut vars = Variables::new();
+    vars.set("CONST", "a \"b\" $c".into()).unwrap();
+    vars.set_readonly("CONST");
+
+    assert!(vars.set("CONST", "other".into()).is_err());
+    assert!(vars.unset("CONST").is_err());
+    assert_eq!(
+        vars.declaration("CONST").unwrap(),
+        r#"declare -r CONST="a \"b\" \$c""#
+    );
+}